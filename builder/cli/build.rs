@@ -40,12 +40,9 @@ fn main() {
 
     let version_code = format!("    version = \"{}\",", version);
     let content = std::fs::read_to_string(&cli_path).unwrap();
-    let mut lines: Vec<&str> = content.lines().map(|s| s).collect();
-    for i in 0..lines.len() {
-        if lines[i].contains("version") {
-            lines[i] = &version_code;
-            break;
-        }
+    let mut lines: Vec<&str> = content.lines().collect();
+    if let Some(line) = lines.iter_mut().find(|l| l.contains("version")) {
+        *line = &version_code;
     }
     std::fs::write(cli_path, lines.join("\n") + "\n").unwrap();
     println!("success patch consts version to {version}");
@@ -1,28 +1,157 @@
+use anyhow::Result;
 use clap::{Parser, Subcommand, ValueHint};
+use place::Context;
 use std::path::PathBuf;
 
 use utils::log::setup_tracing;
 
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// check the config files and the rules in them
+    Check,
+    /// print the effective config merged from the config files
+    Show,
+}
+
+#[derive(Subcommand, Debug)]
+enum ReviewCommands {
+    /// list the archived files without any datetime in metadata
+    List,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// place files into directories by datetime
     Place {
-        /// input file/directory path
-        #[arg(short, long, value_hint = ValueHint::FilePath)]
-        input: PathBuf,
+        /// input file/directory path, can be specified multiple times
+        #[arg(short, long, value_hint = ValueHint::FilePath, required_unless_present_any = ["input_list", "files_from"])]
+        input: Vec<PathBuf>,
+        /// a file listing the input paths, one per line
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        input_list: Option<PathBuf>,
+        /// place only the files listed in the file (newline or NUL delimited), `-` to read from stdin
+        #[arg(long, value_hint = ValueHint::FilePath, conflicts_with_all = ["input", "input_list"])]
+        files_from: Option<PathBuf>,
+        /// the base directory to resolve the relative paths in `--files-from`, default is current directory
+        #[arg(long, value_hint = ValueHint::DirPath, requires = "files_from")]
+        files_base: Option<PathBuf>,
+        /// follow symbolic links when walking the input directories
+        #[arg(long, default_value = "false")]
+        follow_links: bool,
+        /// do not cross file system boundaries when walking the input directories
+        #[arg(long, default_value = "false")]
+        same_file_system: bool,
+        /// the maximum depth to walk the input directories
+        #[arg(long)]
+        max_depth: Option<usize>,
         /// test mode, do not copy/move file
         #[arg(long, default_value = "false")]
         test: bool,
         /// rename the file name by datetime(%Y-%m-%d)
         #[arg(long, default_value = "false")]
         rename_with_ymd: bool,
+        /// force to rehash all files and verify the placed files, ignore the source index
+        #[arg(long, default_value = "false")]
+        rehash: bool,
+        /// place the files without any datetime in metadata into `unknown-date/`
+        #[arg(long, default_value = "false")]
+        unknown_date: bool,
+    },
+    /// rehash the archived files after changing the hash algorithm in config
+    MigrateHash {
+        /// delete the records whose archived file is missing or modified
+        #[arg(long, default_value = "false")]
+        prune: bool,
+    },
+    /// report the near-duplicate images in the archive by perceptual hash
+    Dupes {
+        /// the minimum similarity (0-1) of two images to be treated as duplicates
+        #[arg(long, default_value = "0.9")]
+        threshold: f64,
+        /// which one of the duplicates to keep
+        #[arg(long, value_enum, default_value = "resolution")]
+        keep: place::KeepPolicy,
+    },
+    /// list the files in the input directories which are not archived yet
+    Report {
+        /// input directory path, can be specified multiple times
+        #[arg(short, long, value_hint = ValueHint::DirPath, required = true)]
+        input: Vec<PathBuf>,
+    },
+    /// list all the source paths of an archived file
+    Origin {
+        /// the file path or hash
+        target: String,
+    },
+    /// list the runs, or the changes made by a run
+    History {
+        /// the run id to show the changes
+        #[arg(long)]
+        run: Option<i64>,
+    },
+    /// undo the changes made by a run
+    Undo {
+        /// the run id to undo, see `history`
+        #[arg(long)]
+        run: i64,
+    },
+    /// verify the archived files with the database
+    Verify {
+        /// re-copy the missing or modified files from the sources and re-apply the times
+        #[arg(long, default_value = "false")]
+        repair: bool,
+    },
+    /// index an existing archive into the database
+    Index {
+        /// parse the metadata for the earliest datetime instead of using the file modified time
+        #[arg(long, default_value = "false")]
+        parse: bool,
+    },
+    /// reorganize the archive by the current layout
+    Relayout {
+        /// rename the file name by datetime(%Y-%m-%d)
+        #[arg(long, default_value = "false")]
+        rename_with_ymd: bool,
+        /// only print the moves
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+    },
+    /// assign the date to a file, the archived file is moved into the new directory
+    SetDate {
+        /// the file path or hash
+        #[arg(required_unless_present = "csv", requires = "date")]
+        target: Option<String>,
+        /// the date in local time, YYYY-MM-DD[ HH:MM:SS], YYYY-MM or YYYY
+        date: Option<String>,
+        /// a csv file of `<path|hash>,<date>` lines to assign the dates in bulk
+        #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "target")]
+        csv: Option<PathBuf>,
+    },
+    /// review the files placed by the file times only
+    Review {
+        #[command(subcommand)]
+        command: ReviewCommands,
+    },
+    /// check or show the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// find duplicate files
+    Dupf {
+        /// input file/directory path, can be specified multiple times, the files in the first one are kept
+        #[arg(short, long, value_hint = ValueHint::FilePath, required = true)]
+        input: Vec<PathBuf>,
+        /// the output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: place::DupfFormat,
+        /// the action to apply to the duplicates, dry run without `--yes`
+        #[arg(long, value_enum)]
+        action: Option<place::DupfAction>,
+        /// confirm to apply the action
+        #[arg(long, default_value = "false", requires = "action")]
+        yes: bool,
     },
-    // /// find duplicate files
-    // Dupf {
-    //     /// input file/directory path
-    //     #[arg(short, long, value_hint = ValueHint::FilePath)]
-    //     input: PathBuf,
-    // },
 }
 
 /// Simple program to greet a person
@@ -39,6 +168,12 @@ struct Cli {
     /// output directory path
     #[arg(short, long, global=true, value_hint = ValueHint::FilePath)]
     output: Option<PathBuf>,
+    /// the config file merged over the system/user/project configs, or set `MMFPLACE_CONFIG`
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
+    /// the database path, default is `.mmfplace/place.db` in the output directory
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    database: Option<PathBuf>,
     /// enable verbose logging
     #[arg(short, long, global = true)]
     verbose: bool,
@@ -47,6 +182,88 @@ struct Cli {
     logfile: Option<PathBuf>,
 }
 
+fn print_lines(lines: &[String]) {
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+// 必须指定输出目录，并使用该输出目录的数据库
+fn output_required(args: &Cli) -> Result<(Context, PathBuf)> {
+    let output = args
+        .output
+        .as_ref()
+        .ok_or(anyhow::anyhow!("the output directory must be specified"))?
+        .canonicalize()?;
+    let ctx = Context::from_args(
+        args.config.as_deref(),
+        args.database.as_deref(),
+        Some(&output),
+    )?;
+    Ok((ctx, output))
+}
+
+// 指定输出目录时使用该输出目录的数据库，否则使用默认的数据库
+fn output_optional(args: &Cli) -> Result<(Context, Option<PathBuf>)> {
+    match args.output {
+        Some(_) => output_required(args).map(|(ctx, o)| (ctx, Some(o))),
+        None => {
+            let ctx = Context::from_args(args.config.as_deref(), args.database.as_deref(), None)?;
+            Ok((ctx, None))
+        }
+    }
+}
+
+async fn process(
+    args: &Cli,
+    inputs: place::Inputs,
+    test: bool,
+    rename_with_ymd: bool,
+    rehash: bool,
+    unknown_date: bool,
+) -> Result<()> {
+    if matches!(&inputs, place::Inputs::Roots { roots, .. } if roots.is_empty()) {
+        return Err(anyhow::anyhow!("no input specified"));
+    }
+    // 单个输入目录时默认输出到 `input.mmfplace`，多个输入或文件列表时无法确定，必须指定输出目录
+    let output = match (&args.output, &inputs) {
+        (Some(o), _) => o.to_owned(),
+        (None, place::Inputs::Roots { roots, .. }) if roots.len() == 1 => {
+            roots[0].with_extension("mmfplace")
+        }
+        (None, _) => {
+            return Err(anyhow::anyhow!(
+                "the output directory must be specified with multiple inputs or a file list"
+            ));
+        }
+    };
+    if !output.is_dir() {
+        std::fs::create_dir_all(&output)?;
+    }
+    let output = output.canonicalize()?;
+    // test mode 不读写数据库
+    let ctx = match test {
+        true => Context::from_args(args.config.as_deref(), None, None)?,
+        false => Context::from_args(
+            args.config.as_deref(),
+            args.database.as_deref(),
+            Some(&output),
+        )?,
+    };
+    // 终端中在底部显示进度，否则只输出日志
+    let options = place::PlaceOptions::new(output)
+        .inputs(inputs)
+        .test(test)
+        .rename_with_ymd(rename_with_ymd)
+        .rehash(rehash)
+        .unknown_date(unknown_date)
+        .terminal_progress();
+    let summary = place::process_with(&ctx, options).await?;
+    print_lines(&summary.lines());
+    // 失败的文件不影响其他文件的归档，但命令需要以非零状态退出
+    summary.check()
+}
+
 #[tokio::main]
 async fn main() {
     // env_logger::init_from_env(Env::default().filter_or("LOG_LEVEL", "info"));
@@ -58,16 +275,230 @@ async fn main() {
     match &args.command {
         Commands::Place {
             input,
+            input_list,
+            files_from,
+            files_base,
+            follow_links,
+            same_file_system,
+            max_depth,
             test,
             rename_with_ymd,
+            rehash,
+            unknown_date,
         } => {
-            if let Err(e) = place::process(input, &args.output, *test, *rename_with_ymd).await {
+            let inputs = if let Some(from) = files_from {
+                let base = files_base.clone().unwrap_or(PathBuf::from("."));
+                place::read_files_from(from, &base).map(place::Inputs::Files)
+            } else {
+                let walk = place::WalkOptions {
+                    follow_links: *follow_links,
+                    same_file_system: *same_file_system,
+                    max_depth: *max_depth,
+                };
+                let mut roots = input.clone();
+                match input_list {
+                    Some(list) => place::read_input_list(list).map(|l| {
+                        roots.extend(l);
+                        place::Inputs::Roots { roots, walk }
+                    }),
+                    None => Ok(place::Inputs::Roots { roots, walk }),
+                }
+            };
+            let inputs = match inputs {
+                Ok(i) => i,
+                Err(e) => {
+                    tracing::error!(error = ?e, "read inputs failed");
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = process(
+                &args,
+                inputs,
+                *test,
+                *rename_with_ymd,
+                *rehash,
+                *unknown_date,
+            )
+            .await
+            {
                 tracing::error!(error = ?e, "process failed");
                 std::process::exit(1);
             }
-        } // Commands::Dupf { input } => {
-          //     dupf::process(input, &args.output);
-          // }
+        }
+        Commands::MigrateHash { prune } => {
+            let ret = async {
+                let (ctx, output) = output_required(&args)?;
+                place::migrate_hash(&ctx, &output, *prune).await
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "migrate hash failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Dupes { threshold, keep } => {
+            let ret = async {
+                let (ctx, output) = output_required(&args)?;
+                let report = place::dupes(&ctx, &output, *threshold, *keep).await?;
+                print_lines(&report.lines());
+                anyhow::Ok(())
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "find duplicates failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Report { input } => {
+            let ret = async {
+                let (ctx, output) = output_optional(&args)?;
+                let report = place::report(&ctx, input.clone(), output.as_deref()).await?;
+                print_lines(&report.lines());
+                // 有无法遍历的输入目录时不能安全清理，以非零状态退出
+                report.check()
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "report failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Origin { target } => {
+            let ret = output_optional(&args)
+                .and_then(|(ctx, _)| place::origin(&ctx, target))
+                .map(|r| print_lines(&r.lines()));
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "find origin failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::History { run } => {
+            let ret = output_optional(&args)
+                .and_then(|(ctx, _)| place::history(&ctx, *run))
+                .map(|r| print_lines(&r.lines()));
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "show history failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Undo { run } => {
+            let ret = output_optional(&args)
+                .and_then(|(ctx, _)| place::undo(&ctx, *run))
+                .map(|r| print_lines(&r.lines()));
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "undo failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Verify { repair } => {
+            let ret = async {
+                let (ctx, output) = output_required(&args)?;
+                let report = place::verify(&ctx, &output, *repair).await?;
+                print_lines(&report.lines());
+                anyhow::Ok(())
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "verify failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Index { parse } => {
+            let ret = async {
+                let (ctx, output) = output_required(&args)?;
+                let report = place::index(&ctx, &output, *parse).await?;
+                print_lines(&report.lines());
+                anyhow::Ok(())
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "index failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Relayout {
+            rename_with_ymd,
+            dry_run,
+        } => {
+            let ret = output_required(&args)
+                .and_then(|(ctx, output)| {
+                    place::relayout(&ctx, &output, *rename_with_ymd, *dry_run)
+                })
+                .map(|r| print_lines(&r.lines()));
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "relayout failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::SetDate { target, date, csv } => {
+            let ret = output_required(&args).and_then(|(ctx, output)| {
+                place::set_date(
+                    &ctx,
+                    &output,
+                    target.as_deref(),
+                    date.as_deref(),
+                    csv.as_deref(),
+                )
+            });
+            // 失败的条目不影响其他条目，但命令需要以非零状态退出
+            let ret = ret.and_then(|r| {
+                print_lines(&r.lines());
+                r.check()
+            });
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "set date failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Review { command } => {
+            let ret = output_required(&args).and_then(|(ctx, _)| match command {
+                ReviewCommands::List => place::review_list(&ctx).map(|r| print_lines(&r.lines())),
+            });
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "review failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Config { command } => {
+            let ret = match command {
+                ConfigCommands::Check => {
+                    place::config_check(args.config.as_deref()).and_then(|r| {
+                        print_lines(&r.lines());
+                        r.check()
+                    })
+                }
+                ConfigCommands::Show => place::config_show(args.config.as_deref()).and_then(|c| {
+                    if c.sources.is_empty() {
+                        println!("# the built-in default");
+                    }
+                    for f in c.sources.iter() {
+                        println!("# merged from {}", f.display());
+                    }
+                    println!("{}", c.to_toml().map_err(anyhow::Error::msg)?);
+                    Ok(())
+                }),
+            };
+            if let Err(e) = ret {
+                tracing::error!(error = ?e, "config failed");
+                std::process::exit(1);
+            }
+        }
+        Commands::Dupf {
+            input,
+            format,
+            action,
+            yes,
+        } => {
+            let ret = async {
+                let (ctx, _) = output_optional(&args)?;
+                let report = place::dupf(&ctx, input.clone()).await?;
+                print_lines(&report.lines(*format)?);
+                // 先输出所有的分组，再执行 action
+                match action {
+                    Some(action) => report.apply(*action, *yes),
+                    None => Ok(()),
+                }
+            };
+            if let Err(e) = ret.await {
+                tracing::error!(error = ?e, "find duplicate files failed");
+                std::process::exit(1);
+            }
+        }
     };
     std::process::exit(0);
 }
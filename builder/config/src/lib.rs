@@ -3,8 +3,11 @@ use regex::{Error, Regex};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
-pub static CONFIG: Lazy<Config> = Lazy::new(|| Config::new());
-const CONFIG_DEFAULT: &str = include_str!("default.toml");
+/// the built-in default config, the config files are merged by `Config::load`.
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::new);
+pub const CONFIG_DEFAULT: &str = include_str!("default.toml");
+
+const APP_NAME: &str = "mmfplace";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StripTime {
@@ -12,35 +15,49 @@ pub struct StripTime {
     pub test: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Capture {
     /// check the string in target or not.
     pub check: String,
     /// the regex to match the string.
-    #[serde(deserialize_with = "deserialize_regex")]
+    #[serde(
+        serialize_with = "serialize_regex",
+        deserialize_with = "deserialize_regex"
+    )]
     pub regex: Regex,
     #[serde(default = "capture_index")]
     pub index: Option<u8>,
 }
 
+// 配置文件可以只包含部分字段，缺少的字段由低优先级的配置补全
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DateParse {
     pub ignore: Option<Vec<String>>,
     pub list: Vec<StripTime>,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DateRegex {
     pub ignore: Option<Vec<String>>,
     pub list: Vec<Capture>,
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TypeRegex {
     pub ignore: Option<Vec<String>>,
     pub list: Vec<Capture>,
 }
 
+fn serialize_regex<S>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(regex.as_str())
+}
+
 fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -53,20 +70,28 @@ fn capture_index() -> Option<u8> {
     Some(1)
 }
 
-#[derive(Debug, Default, Clone, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     // the number of files to process in a batch
     pub batch: Option<u8>,
     // the java executable path, default is java an ensure exist in $PATH
     pub java: Option<String>,
-    // the database path, default is current executable directory named place.db
+    // the database path, default is `.mmfplace/place.db` in the output directory
     pub database: Option<PathBuf>,
+    // the hash algorithm used to dedupe files, one of md5, sha256, blake3, xxh3, default is md5
+    pub hash: Option<String>,
+    // what to do with the modified archived files, one of skip, rename, overwrite, trash, default is trash
+    pub conflict: Option<String>,
     pub dateparse: DateParse,
     pub dateregex: DateRegex,
     pub typeregex: TypeRegex,
+    /// the config files loaded, from low to high priority
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
 }
 
-static CURRENT_FILE: Lazy<fn(&str) -> PathBuf> = Lazy::new(|| {
+pub static CURRENT_FILE: Lazy<fn(&str) -> PathBuf> = Lazy::new(|| {
     |n| {
         let mut work_dir =
             std::env::current_exe().expect("failed to get current execute directory");
@@ -75,28 +100,126 @@ static CURRENT_FILE: Lazy<fn(&str) -> PathBuf> = Lazy::new(|| {
     }
 });
 
+// $XDG_*_HOME 未设置时使用 $HOME 下的默认目录，windows 使用 %APPDATA% 或 %LOCALAPPDATA%
+fn xdg_home(env: &str, default: &str, windows: &str) -> PathBuf {
+    let home = std::env::var_os(env)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            if cfg!(windows) {
+                std::env::var_os(windows).map(PathBuf::from)
+            } else {
+                std::env::var_os("HOME").map(|h| PathBuf::from(h).join(default))
+            }
+        });
+    match home {
+        Some(home) => home.join(APP_NAME),
+        // 都没有时退回到程序目录
+        None => CURRENT_FILE(""),
+    }
+}
+
+/// the user config directory, `$XDG_CONFIG_HOME/mmfplace`.
+pub fn config_dir() -> PathBuf {
+    xdg_home("XDG_CONFIG_HOME", ".config", "APPDATA")
+}
+
+/// the user data directory, `$XDG_DATA_HOME/mmfplace`.
+pub fn data_dir() -> PathBuf {
+    xdg_home("XDG_DATA_HOME", ".local/share", "LOCALAPPDATA")
+}
+
+/// the cache directory, `$XDG_CACHE_HOME/mmfplace`.
+pub fn cache_dir() -> PathBuf {
+    xdg_home("XDG_CACHE_HOME", ".cache", "LOCALAPPDATA")
+}
+
+// 系统配置 $XDG_CONFIG_DIRS 中靠前的优先级更高
+fn system_configs() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        return vec![];
+    }
+    let dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or("/etc/xdg".to_string());
+    dirs.split(':')
+        .rev()
+        .filter(|d| Path::new(d).is_absolute())
+        .map(|d| Path::new(d).join(APP_NAME).join("config.toml"))
+        .collect()
+}
+
+/// the config files by priority from low to high, the ones not exist are ignored except the specified one:
+/// system(`$XDG_CONFIG_DIRS`, `config.toml` beside the executable) < user(`$XDG_CONFIG_HOME`)
+/// < project(`.mmfplace/config.toml` in the current directory) < `config` (`--config`) or `$MMFPLACE_CONFIG`.
+pub fn config_files(config: Option<&Path>) -> Vec<PathBuf> {
+    let mut files = system_configs();
+    files.push(CURRENT_FILE("config.toml"));
+    files.push(config_dir().join("config.toml"));
+    files.push(PathBuf::from(".mmfplace").join("config.toml"));
+    files.retain(|f| f.is_file());
+    // 指定的配置文件不存在时读取会报错
+    files.extend(
+        config
+            .map(Path::to_path_buf)
+            .or(std::env::var_os("MMFPLACE_CONFIG").map(PathBuf::from)),
+    );
+    files
+}
+
+// 表合并，其他的值（包括数组）直接替换
+fn merge(base: &mut toml::Table, other: toml::Table) {
+    for (k, v) in other {
+        match (base.get_mut(&k), v) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge(b, o),
+            (_, v) => {
+                base.insert(k, v);
+            }
+        }
+    }
+}
+
 impl Config {
-    /// load config.toml
+    /// the built-in default config, without any config file.
     pub fn new() -> Self {
-        let config = CURRENT_FILE("config.toml");
-        if !config.is_file() {
-            std::fs::write(&config, CONFIG_DEFAULT).expect("Failed to write config.toml");
-            log::warn!(
-                "🚨 The first run creates a default config file at {}",
-                config.display()
-            )
-        }
-        Self::load_from_file(config.as_path())
+        Self::try_load_from_files(&[]).expect("Failed to load the default config")
     }
 
-    pub fn load_from_file(f: &Path) -> Self {
-        log::debug!("Loading config from: {}", f.display());
-        let content = std::fs::read_to_string(f).expect("Failed to read config.toml");
-        let mut cfg: Config = toml::from_str(&content).expect("Failed to parse config.toml");
+    /// load the built-in default config and merge the config files, see `config_files`.
+    pub fn load(config: Option<&Path>) -> Result<Self, String> {
+        Self::try_load_from_files(&config_files(config))
+    }
+
+    /// the effective config in toml, see `config show`.
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize config: {}", e))
+    }
+
+    /// load the config files, the error contains the file path and the line/column of the error.
+    pub fn try_load_from_files(files: &[PathBuf]) -> Result<Self, String> {
+        let mut table: toml::Table =
+            toml::from_str(CONFIG_DEFAULT).expect("Failed to parse the default config");
+        for f in files {
+            log::debug!("Loading config from: {}", f.display());
+            let content = std::fs::read_to_string(f)
+                .map_err(|e| format!("Failed to read {}: {}", f.display(), e))?;
+            // 先单独解析每个文件，合并之后的错误无法定位到文件中的位置
+            toml::from_str::<Config>(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", f.display(), e))?;
+            let other = toml::from_str(&content)
+                .map_err(|e| format!("Failed to parse {}: {}", f.display(), e))?;
+            merge(&mut table, other);
+        }
+        let mut cfg: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Failed to load config: {}", e))?;
+        cfg.sources = files.to_vec();
         cfg.batch = Some(cfg.batch.unwrap_or(10));
         cfg.java = Some(cfg.java.unwrap_or("java".to_string()));
-        cfg.database = Some(cfg.database.unwrap_or(CURRENT_FILE("place.db")));
-        cfg
+        cfg.hash = Some(cfg.hash.unwrap_or("md5".to_string()));
+        cfg.conflict = Some(cfg.conflict.unwrap_or("trash".to_string()));
+        Ok(cfg)
     }
 }
 
@@ -106,16 +229,10 @@ impl Capture {
         let re = &self.regex;
         match re.captures(text) {
             Some(caps) => match caps.get(self.index.unwrap() as usize) {
-                Some(cap) => {
-                    return Ok(cap.as_str().trim().to_owned());
-                }
-                None => {
-                    return Err(Error::Syntax("capture index out of range".to_owned()));
-                }
+                Some(cap) => Ok(cap.as_str().trim().to_owned()),
+                None => Err(Error::Syntax("capture index out of range".to_owned())),
             },
-            None => {
-                return Err(Error::Syntax("no capture found".to_owned()));
-            }
+            None => Err(Error::Syntax("no capture found".to_owned())),
         }
     }
 }
@@ -128,6 +245,8 @@ mod tests {
     fn test_config() {
         println!("config: {:#?}", *CONFIG);
         assert_eq!(CONFIG.batch, Some(10));
+        assert_eq!(CONFIG.hash.as_deref(), Some("md5"));
+        assert_eq!(CONFIG.conflict.as_deref(), Some("trash"));
         assert!(!CONFIG.dateparse.list.is_empty());
         assert!(!CONFIG.dateregex.list.is_empty());
         assert!(CONFIG.dateregex.ignore.is_some());
@@ -151,4 +270,46 @@ mod tests {
             println!("text: {}, result: {:?}", text, c);
         }
     }
+
+    #[test]
+    fn test_load_from_files() {
+        let dir = std::env::temp_dir().join("mmfplace_test_load_from_files");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (user, project) = (dir.join("user.toml"), dir.join("project.toml"));
+        std::fs::write(
+            &user,
+            "batch = 4\nhash = \"xxh3\"\n[typeregex]\nignore = []\n",
+        )
+        .unwrap();
+        std::fs::write(&project, "batch = 2\n").unwrap();
+
+        let cfg = Config::try_load_from_files(&[user.clone(), project.clone()]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cfg.batch, Some(2));
+        assert_eq!(cfg.hash.as_deref(), Some("xxh3"));
+        assert_eq!(cfg.conflict.as_deref(), Some("trash"));
+        // the nested table is merged, the array is replaced
+        assert_eq!(cfg.typeregex.ignore, Some(vec![]));
+        assert!(!cfg.typeregex.list.is_empty());
+        assert!(!cfg.dateparse.list.is_empty());
+        assert_eq!(cfg.sources, vec![user, project]);
+    }
+
+    #[test]
+    fn test_load_error() {
+        let f = std::env::temp_dir().join("mmfplace_test_load_error.toml");
+        std::fs::write(
+            &f,
+            "batch = 4\n[typeregex]\nlist = [\n  { check = \"a\", regex = \"(b\" },\n]\n",
+        )
+        .unwrap();
+        let e = Config::try_load_from_files(std::slice::from_ref(&f)).unwrap_err();
+        std::fs::remove_file(&f).unwrap();
+        println!("{}", e);
+        assert!(e.contains("line 4"));
+        assert!(e.contains("regex parse error"));
+        assert!(Config::try_load_from_files(&[PathBuf::from("/not/exist.toml")]).is_err());
+        // the specified config file must exist
+        assert!(Config::load(Some(Path::new("/not/exist.toml"))).is_err());
+    }
 }
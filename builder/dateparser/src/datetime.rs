@@ -4,13 +4,15 @@ use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use once_cell::sync::Lazy;
 use regex::Regex;
 
-use config::CONFIG;
+use config::{CONFIG, StripTime};
 
 /// Parse struct has methods implemented parsers for accepted formats.
 #[allow(dead_code)]
 pub struct Parse<'z, Tz2> {
     tz: &'z Tz2,
     default_time: Option<NaiveTime>,
+    // the strftime formats to try, see config `dateparse`
+    formats: &'z [StripTime],
 }
 
 impl<'z, Tz2> Parse<'z, Tz2>
@@ -20,7 +22,20 @@ where
     /// Create a new instrance of [`Parse`] with a custom parsing timezone that handles the
     /// datetime string without time offset.
     pub fn new(tz: &'z Tz2, default_time: Option<NaiveTime>) -> Self {
-        Self { tz, default_time }
+        Self::with_formats(tz, default_time, &CONFIG.dateparse.list)
+    }
+
+    /// Create a new instrance of [`Parse`] with the formats instead of the global config.
+    pub fn with_formats(
+        tz: &'z Tz2,
+        default_time: Option<NaiveTime>,
+        formats: &'z [StripTime],
+    ) -> Self {
+        Self {
+            tz,
+            default_time,
+            formats,
+        }
     }
 
     // https://stackoverflow.com/questions/61179070/rust-chrono-parse-date-string-parseerrornotenough-and-parseerrortooshort/61179071#61179071
@@ -39,12 +54,12 @@ where
     // { "fmt" = "%Y:%m:%d", "test" = "2010:06:24" },
     fn ymd(&self, input: &str) -> Result<DateTime<Utc>> {
         if input.len() == 10 {
-            for strip in CONFIG.dateparse.list.iter() {
-                if strip.fmt.len() == 8 {
-                    if let Ok(d) = NaiveDate::parse_from_str(input, &strip.fmt) {
-                        // NaiveDate 转为 DateTime<Utc>
-                        return Ok(Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()));
-                    }
+            for strip in self.formats.iter() {
+                if strip.fmt.len() == 8
+                    && let Ok(d) = NaiveDate::parse_from_str(input, &strip.fmt)
+                {
+                    // NaiveDate 转为 DateTime<Utc>
+                    return Ok(Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()));
                 }
             }
         }
@@ -57,12 +72,12 @@ where
     // { "fmt" = "%Y/%m/%d %H:%M:%S", "test" = "2017/08/16 12:18:36" },
     pub fn ymd_hms(&self, input: &str) -> Result<DateTime<Utc>> {
         if input.len() == 19 {
-            for strip in CONFIG.dateparse.list.iter() {
-                if strip.fmt.len() == 17 {
-                    if let Ok(dt) = NaiveDateTime::parse_from_str(input, &strip.fmt) {
-                        // NaiveDateTime 转为 DateTime<Utc>
-                        return Ok(Utc.from_utc_datetime(&dt));
-                    }
+            for strip in self.formats.iter() {
+                if strip.fmt.len() == 17
+                    && let Ok(dt) = NaiveDateTime::parse_from_str(input, &strip.fmt)
+                {
+                    // NaiveDateTime 转为 DateTime<Utc>
+                    return Ok(Utc.from_utc_datetime(&dt));
                 }
             }
         }
@@ -75,7 +90,7 @@ where
     // { "fmt" = "%Y-%m-%d %H:%M:%S% %Z", "test" = "2017-08-16 12:18:36 UTC" },
     fn ymd_hms_tz(&self, input: &str) -> Result<DateTime<Utc>> {
         if input.len() > 19 {
-            for strip in CONFIG.dateparse.list.iter() {
+            for strip in self.formats.iter() {
                 if strip.fmt.len() > 17 {
                     if let Ok(dt) = DateTime::parse_from_str(input, &strip.fmt) {
                         // DateTime<Tz> 转为 DateTime<Utc>
@@ -104,11 +119,11 @@ where
     // RFC3339 = Date + Time + TimeZone, YYYY-MM-DDTHH:MM:SS[.ffffff]Z 或 YYYY-MM-DDTHH:MM:SS[.ffffff]±HH:MM
     // "2001-07-08T00:08:56+05:00";
     fn rfc3339(&self, input: &str) -> Result<DateTime<Utc>> {
-        if input.len() > 20 {
-            if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
-                // DateTime<Tz> 转为 DateTime<Utc>
-                return Ok(dt.with_timezone(&Utc));
-            }
+        if input.len() > 20
+            && let Ok(dt) = DateTime::parse_from_rfc3339(input)
+        {
+            // DateTime<Tz> 转为 DateTime<Utc>
+            return Ok(dt.with_timezone(&Utc));
         }
         Err(anyhow!("DateTime::parse_from_rfc3339 failed"))
     }
@@ -117,11 +132,11 @@ where
     // "Tue, 1 Jul 2003 10:52:37 +0200";
     // "Wed, 30 Nov 2022 05:58:56 +0100"
     fn rfc2822(&self, input: &str) -> Result<DateTime<Utc>> {
-        if input.len() > 20 {
-            if let Ok(dt) = DateTime::parse_from_rfc2822(input) {
-                // DateTime<Tz> 转为 DateTime<Utc>
-                return Ok(dt.with_timezone(&Utc));
-            }
+        if input.len() > 20
+            && let Ok(dt) = DateTime::parse_from_rfc2822(input)
+        {
+            // DateTime<Tz> 转为 DateTime<Utc>
+            return Ok(dt.with_timezone(&Utc));
         }
         Err(anyhow!("DateTime::parse_from_rfc2822 failed"))
     }
@@ -131,7 +146,7 @@ where
     //
     fn non_standard(&self, input: &str) -> Result<DateTime<Utc>> {
         // dbg!("non-standard: {}", input);
-        for strip in CONFIG.dateparse.list.iter() {
+        for strip in self.formats.iter() {
             if strip.fmt.len() > 9 {
                 if let Ok(dt) = DateTime::parse_from_str(input, &strip.fmt) {
                     // DateTime<Tz> 转为 DateTime<Utc>
@@ -150,13 +165,13 @@ where
     pub(crate) fn force_ymd(&self, input: &str) -> Result<DateTime<Utc>> {
         static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d{4}[-:/]\d{2}[-:/]\d{2})").unwrap());
 
-        if let Some(caps) = RE.captures(input) {
-            if let Some(c) = caps.get(0) {
-                let date = c.as_str().replacen(":", "-", 2).replacen("/", "-", 2);
+        if let Some(caps) = RE.captures(input)
+            && let Some(c) = caps.get(0)
+        {
+            let date = c.as_str().replacen(":", "-", 2).replacen("/", "-", 2);
 
-                if let Ok(d) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
-                    return Ok(Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()));
-                }
+            if let Ok(d) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+                return Ok(Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()));
             }
         }
         Err(anyhow!("Force parsed with ymd failed"))
@@ -9,6 +9,11 @@ pub fn parse(input: &str) -> Result<DateTime<Utc>> {
     Parse::new(&Local, None).parse(input)
 }
 
+/// parse with the formats instead of the global config, see config `dateparse`.
+pub fn parse_with_formats(input: &str, formats: &[config::StripTime]) -> Result<DateTime<Utc>> {
+    Parse::with_formats(&Local, None, formats).parse(input)
+}
+
 pub fn parse_with_timezone<Tz2: TimeZone>(input: &str, tz: &Tz2) -> Result<DateTime<Utc>> {
     Parse::new(tz, None).parse(input)
 }
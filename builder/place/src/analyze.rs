@@ -0,0 +1,146 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use super::context::Context;
+use super::media::MediaInfo;
+use super::process::parse_metadata;
+use super::target::{Provenance, Target};
+use utils::crypto::get_file_hash;
+
+/// a datetime parsed from a line of the metadata.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParsedTime {
+    pub datetime: DateTime<Utc>,
+    /// the metadata line, `[Directory] Tag = Value`
+    pub text: String,
+}
+
+/// the result of analyzing a file, and where it is placed if any.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    /// the source file
+    pub path: PathBuf,
+    pub hash: String,
+    pub algorithm: String,
+    /// the file type parsed from metadata, e.g. `jpg`, the extension is used if not found
+    pub ftype: Option<String>,
+    pub extension: String,
+    /// the earliest datetime used to place the file
+    pub earliest: Option<DateTime<Local>>,
+    pub provenance: Provenance,
+    /// all the datetimes parsed from metadata
+    pub parsed: Vec<ParsedTime>,
+    /// the file attribute times
+    pub accessed: Option<DateTime<Local>>,
+    pub modified: Option<DateTime<Local>>,
+    pub created: Option<DateTime<Local>>,
+    pub media: MediaInfo,
+    /// the path in the output, none if not placed
+    pub output: Option<PathBuf>,
+}
+
+impl From<&Target> for FileReport {
+    fn from(target: &Target) -> Self {
+        let attr = |i: usize| {
+            target
+                .get_attrtime()
+                .get(i)
+                .copied()
+                .flatten()
+                .map(DateTime::<Local>::from)
+        };
+        FileReport {
+            path: target.path.clone(),
+            hash: target.hash.clone(),
+            algorithm: target.algorithm.clone(),
+            ftype: target.ftype.clone(),
+            extension: target.extension.clone(),
+            earliest: target.get_earliest().ok(),
+            provenance: target.get_provenance(),
+            parsed: target
+                .get_parsedtime()
+                .iter()
+                .zip(target.get_parsedtexts())
+                .map(|(dt, text)| ParsedTime {
+                    datetime: *dt,
+                    text: text.clone(),
+                })
+                .collect(),
+            accessed: attr(0),
+            modified: attr(1),
+            created: attr(2),
+            media: target.media.clone(),
+            output: Some(target.output.clone()).filter(|o| !o.as_os_str().is_empty()),
+        }
+    }
+}
+
+/// hash the file with the algorithm of the context.
+pub fn hash_file(ctx: &Context, path: &Path) -> Result<String> {
+    Ok(get_file_hash(ctx.hash_algorithm(), path)?)
+}
+
+/// parse the datetime from a metadata line by the config of the context, none if ignored or not parsed.
+pub fn parse_datetime(ctx: &Context, text: &str) -> Option<DateTime<Utc>> {
+    let config = ctx.config();
+    if let Some(ignore) = &config.dateregex.ignore
+        && ignore.iter().any(|black| text.contains(black))
+    {
+        return None;
+    }
+    dateparser::parse_with_formats(text, &config.dateparse.list).ok()
+}
+
+/// hash the file and parse its metadata without placing it, the database is not used.
+pub async fn analyze(ctx: &Context, path: &Path) -> Result<FileReport> {
+    let mut target = Target::new(path.to_path_buf(), ctx.hash_algorithm())?;
+    parse_metadata(ctx, &mut target).await?;
+    Ok(FileReport::from(&target))
+}
+
+/// analyze the file and generate the path it would be placed in the output, nothing is copied.
+pub async fn plan(
+    ctx: &Context,
+    path: &Path,
+    output: &Path,
+    rename_with_ymd: bool,
+) -> Result<FileReport> {
+    let mut target = Target::new(path.to_path_buf(), ctx.hash_algorithm())?;
+    parse_metadata(ctx, &mut target).await?;
+    target.set_output_parts(output, rename_with_ymd)?;
+    Ok(FileReport::from(&target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_root() -> PathBuf {
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_path_buf()
+    }
+
+    #[tokio::test]
+    async fn test_analyze() {
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let input = get_root().join("tests/2002/11/simple.png");
+        let report = analyze(&ctx, &input).await.unwrap();
+        println!("report: {:#?}", report);
+        assert_eq!(report.hash, "a18932e314dbb4c81c6fd0e282d81d16");
+        assert_eq!(report.ftype.as_deref(), Some("jpg"));
+        assert_eq!(report.provenance, Provenance::Metadata);
+        assert_eq!(report.parsed.len(), 3);
+        assert!(report.parsed.iter().all(|p| !p.text.is_empty()));
+        assert!(report.modified.is_some());
+        assert!(report.output.is_none());
+
+        let text = &report.parsed[0].text;
+        assert_eq!(parse_datetime(&ctx, text), Some(report.parsed[0].datetime));
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::path::{Path, PathBuf};
+
+use super::target::ConflictPolicy;
+use config::{Capture, Config, StripTime};
+use utils::crypto::{HASH_ALGORITHMS, is_supported_hash};
+
+#[derive(Debug, Default)]
+struct Diagnostics {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Diagnostics {
+    fn error(&mut self, at: &str, message: String) {
+        self.errors.push(format!("{}: {}", at, message));
+    }
+
+    fn warning(&mut self, at: &str, message: String) {
+        self.warnings.push(format!("{}: {}", at, message));
+    }
+}
+
+// 按 fmt 解析 test，与 dateparser 一样没有时区的时间视为 UTC
+fn strip_parse(strip: &StripTime) -> Option<DateTime<Utc>> {
+    let (test, fmt) = (strip.test.as_str(), strip.fmt.as_str());
+    DateTime::parse_from_str(test, fmt)
+        .map(|d| d.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(test, fmt)
+                .ok()
+                .map(|t| Utc.from_utc_datetime(&t))
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(test, fmt)
+                .ok()
+                .map(|d| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap()))
+        })
+}
+
+fn check_dateparse(d: &mut Diagnostics, list: &[StripTime]) {
+    if list.is_empty() {
+        d.error("dateparse.list", "no format is configured".to_string());
+    }
+    for (i, strip) in list.iter().enumerate() {
+        let at = format!("dateparse.list[{}]", i);
+        if let Some(j) = list[..i].iter().position(|s| s.fmt == strip.fmt) {
+            d.warning(
+                &at,
+                format!("the fmt `{}` is duplicated with [{}]", strip.fmt, j),
+            );
+            continue;
+        }
+        let Some(expected) = strip_parse(strip) else {
+            d.error(
+                &at,
+                format!(
+                    "the test `{}` does not match the fmt `{}`",
+                    strip.test, strip.fmt
+                ),
+            );
+            continue;
+        };
+        // 解析流程按长度选择格式，test 没有被解析为同样的时间时说明该格式不可达或者被其他格式覆盖
+        match dateparser::parse_with_formats(&strip.test, list) {
+            Ok(got) if got == expected => {}
+            Ok(got) => d.warning(
+                &at,
+                format!(
+                    "the test `{}` is parsed as {} by other rules instead of {}, the fmt is shadowed",
+                    strip.test, got, expected
+                ),
+            ),
+            Err(_) => d.warning(
+                &at,
+                format!(
+                    "the test `{}` is not parsed by the parser, the fmt `{}` is unreachable",
+                    strip.test, strip.fmt
+                ),
+            ),
+        }
+    }
+}
+
+// sample 为包含 check 的一行元数据文本
+fn check_captures(
+    d: &mut Diagnostics,
+    section: &str,
+    list: &[Capture],
+    ignore: &[String],
+    sample: fn(&str) -> String,
+) {
+    for (i, capture) in list.iter().enumerate() {
+        let at = format!("{}.list[{}]", section, i);
+        let index = capture.index.unwrap_or(1) as usize;
+        let groups = capture.regex.captures_len() - 1;
+        if index > groups {
+            d.error(
+                &at,
+                format!(
+                    "the index {} is out of range, the regex `{}` has {} groups",
+                    index, capture.regex, groups
+                ),
+            );
+            continue;
+        }
+        if capture.check.is_empty() {
+            d.warning(&at, "the check is empty, every text is checked".to_string());
+        }
+        if let Some(black) = ignore.iter().find(|b| capture.check.contains(b.as_str())) {
+            d.warning(
+                &at,
+                format!(
+                    "the check contains the ignored `{}`, the rule is unreachable",
+                    black
+                ),
+            );
+            continue;
+        }
+        let text = sample(&capture.check);
+        if capture.capture(&text).is_err() {
+            d.warning(
+                &at,
+                format!(
+                    "the regex `{}` does not match the text `{}`, the rule is unreachable",
+                    capture.regex, text
+                ),
+            );
+            continue;
+        }
+        if let Some(j) = list[..i]
+            .iter()
+            .position(|c| text.contains(&c.check) && c.capture(&text).is_ok())
+        {
+            d.warning(
+                &at,
+                format!("the text `{}` is also matched by [{}] before it", text, j),
+            );
+        }
+    }
+}
+
+fn check_ignore(d: &mut Diagnostics, section: &str, ignore: &[String]) {
+    for (i, black) in ignore.iter().enumerate() {
+        let at = format!("{}.ignore[{}]", section, i);
+        if black.is_empty() {
+            d.error(&at, "the empty string ignores everything".to_string());
+        } else if let Some(j) = ignore[..i].iter().position(|b| b == black) {
+            d.warning(&at, format!("`{}` is duplicated with [{}]", black, j));
+        }
+    }
+}
+
+fn check(config: &Config) -> Diagnostics {
+    let mut d = Diagnostics::default();
+    if config.batch == Some(0) {
+        d.error("batch", "the batch must be greater than 0".to_string());
+    }
+    if let Some(hash) = &config.hash
+        && !is_supported_hash(hash)
+    {
+        d.error(
+            "hash",
+            format!(
+                "unsupported hash algorithm `{}`, expected one of {}",
+                hash,
+                HASH_ALGORITHMS.join(", ")
+            ),
+        );
+    }
+    if let Some(conflict) = &config.conflict
+        && let Err(e) = conflict.parse::<ConflictPolicy>()
+    {
+        d.error("conflict", e.to_string());
+    }
+
+    let empty = vec![];
+    let black = config.dateregex.ignore.as_ref().unwrap_or(&empty);
+    check_dateparse(&mut d, &config.dateparse.list);
+    check_ignore(
+        &mut d,
+        "dateparse",
+        config.dateparse.ignore.as_ref().unwrap_or(&empty),
+    );
+    check_captures(&mut d, "dateregex", &config.dateregex.list, black, |c| {
+        format!("[Directory{}2024-12-20", c)
+    });
+    check_ignore(&mut d, "dateregex", black);
+    // 文件类型同样会跳过 dateregex.ignore 中的文本
+    check_captures(&mut d, "typeregex", &config.typeregex.list, black, |c| {
+        format!("[File Type] {} = jpg", c)
+    });
+    check_ignore(
+        &mut d,
+        "typeregex",
+        config.typeregex.ignore.as_ref().unwrap_or(&empty),
+    );
+    d
+}
+
+/// the result of `place::config_check`, see `check` for the errors.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigReport {
+    /// the config files loaded, from low to high priority
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ConfigReport {
+    /// the lines printed by the `config check` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.files.is_empty() {
+            lines.push("no config file found, the built-in default is used".to_string());
+        }
+        for f in self.files.iter() {
+            lines.push(format!("loaded {}", f.display()));
+        }
+        for e in self.errors.iter() {
+            lines.push(format!("error: {}", e));
+        }
+        for w in self.warnings.iter() {
+            lines.push(format!("warning: {}", w));
+        }
+        lines.push(format!(
+            "{} errors, {} warnings",
+            self.errors.len(),
+            self.warnings.len()
+        ));
+        lines
+    }
+
+    /// the error if the config has any error, the warnings are only reported.
+    pub fn check(&self) -> Result<()> {
+        if !self.errors.is_empty() {
+            return Err(anyhow::anyhow!(
+                "the config has {} errors",
+                self.errors.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 加载并检查配置文件，无法加载时返回错误，规则的错误和警告在结果中
+pub fn do_config_check(config: Option<&Path>) -> Result<ConfigReport> {
+    let files = config::config_files(config);
+    let config = Config::try_load_from_files(&files).map_err(|e| anyhow::anyhow!(e))?;
+    let d = check(&config);
+    Ok(ConfigReport {
+        files,
+        errors: d.errors,
+        warnings: d.warnings,
+    })
+}
+
+// 合并之后生效的配置
+pub fn do_config_show(config: Option<&Path>) -> Result<Config> {
+    Config::load(config).map_err(|e| anyhow::anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let d = check(&config::CONFIG);
+        println!("{:#?}", d);
+        assert!(d.errors.is_empty());
+
+        let mut config = config::CONFIG.clone();
+        config.hash = Some("crc32".to_string());
+        config.dateparse.list.push(StripTime {
+            fmt: "%Y.%m.%d".to_string(),
+            test: "2020-01-01".to_string(),
+        });
+        config.typeregex.list.push(Capture {
+            check: "Expected".to_string(),
+            regex: regex::Regex::new("Expected (.*)").unwrap(),
+            index: Some(2),
+        });
+        config.typeregex.list.push(config.typeregex.list[0].clone());
+        let d = check(&config);
+        println!("{:#?}", d);
+        assert_eq!(d.errors.len(), 3);
+        assert!(d.errors[0].starts_with("hash:"));
+        assert!(d.errors[1].contains("does not match the fmt"));
+        assert!(d.errors[2].contains("out of range"));
+        assert!(
+            d.warnings
+                .iter()
+                .any(|w| w.contains("is also matched by [0]"))
+        );
+    }
+}
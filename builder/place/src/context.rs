@@ -0,0 +1,109 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::db::{check_output, default_database, open_database, output_database};
+use super::target::ConflictPolicy;
+use config::Config;
+
+/// the config and the database used by the commands, cheap to clone.
+///
+/// create a context for each archive to use several configs or databases in one process,
+/// the database is opened on the first use.
+#[derive(Debug, Clone)]
+pub struct Context {
+    config: Arc<Config>,
+    database: PathBuf,
+    conn: Arc<OnceLock<Mutex<Connection>>>,
+}
+
+impl Context {
+    pub fn new(config: Config, database: impl Into<PathBuf>) -> Self {
+        Context {
+            config: Arc::new(config),
+            database: database.into(),
+            conn: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// the context of the output, the database is `.mmfplace/place.db` in the output unless configured,
+    /// and the output is recorded in it.
+    pub fn with_output(config: Config, output: &Path) -> Result<Self> {
+        let database = match config.database.clone() {
+            Some(d) => d,
+            None => output_database(output)?,
+        };
+        let context = Self::new(config, database);
+        check_output(&context.conn()?.lock().unwrap(), &context.database, output)?;
+        Ok(context)
+    }
+
+    /// the context of the command line, the config files are merged with `config` (`--config`),
+    /// see `config::config_files`. The database is `database` (`--database`) if given, otherwise the one
+    /// of the output, see `with_output`, or the default database in the data directory without output.
+    pub fn from_args(
+        config: Option<&Path>,
+        database: Option<&Path>,
+        output: Option<&Path>,
+    ) -> Result<Self> {
+        let mut config = Config::load(config).map_err(|e| anyhow::anyhow!(e))?;
+        if let Some(database) = database {
+            config.database = Some(database.to_path_buf());
+        }
+        match output {
+            Some(output) => Self::with_output(config, output),
+            None => {
+                let database = config.database.clone().unwrap_or_else(default_database);
+                Ok(Self::new(config, database))
+            }
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn database(&self) -> &Path {
+        &self.database
+    }
+
+    // 数据库文件已经存在，不需要处理归档时避免创建空数据库
+    pub fn database_exists(&self) -> bool {
+        self.conn.get().is_some() || self.database.is_file()
+    }
+
+    /// the database connection, opened on the first use.
+    pub fn conn(&self) -> Result<&Mutex<Connection>> {
+        if let Some(conn) = self.conn.get() {
+            return Ok(conn);
+        }
+        let conn = open_database(&self.database)
+            .map_err(|e| e.context(format!("open database {}", self.database.display())))?;
+        // 并发打开时使用先完成的连接
+        Ok(self.conn.get_or_init(|| Mutex::new(conn)))
+    }
+
+    /// the java runtime to read the metadata
+    pub fn java(&self) -> &str {
+        self.config.java.as_deref().unwrap_or("java")
+    }
+
+    /// the hash algorithm used to dedupe files
+    pub fn hash_algorithm(&self) -> &str {
+        self.config.hash.as_deref().unwrap_or("md5")
+    }
+
+    // the invalid value is checked before processing
+    pub fn conflict_policy(&self) -> ConflictPolicy {
+        self.config
+            .conflict
+            .as_deref()
+            .and_then(|c| c.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn batch(&self) -> usize {
+        self.config.batch.unwrap_or(10).max(1) as usize
+    }
+}
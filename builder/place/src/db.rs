@@ -1,10 +1,11 @@
-use rusqlite::{Connection, Result};
-use serde::Serialize;
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::borrow::Cow;
-use std::path::Path;
-use std::sync::{Mutex, OnceLock};
-use tracing::{info, warn};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+use super::media::MediaInfo;
 
 // pub struct FileInfo<'a, 'b, T: AsRef<str> + 'a> {
 //     pub parts: &'a [T],
@@ -13,32 +14,334 @@ use tracing::{info, warn};
 //     pub earliest: i64,
 // }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct FileInfo<'a, T: AsRef<str> + Clone + ToOwned + 'static> {
     // pub parts: Vec<Cow<'static, T>>,
     pub parts: Cow<'a, [T]>,
     pub hash: Cow<'a, str>,
+    // the hash algorithm, see `utils::crypto::HASH_ALGORITHMS`
+    pub algorithm: Cow<'a, str>,
     // the DateTime<Local> timestamp
     pub earliest: i64,
+    // the file size in bytes
+    pub size: Option<i64>,
+    // the source path of the archived file
+    pub source: Option<Cow<'a, str>>,
+    // the start timestamp of the run which first/last saw the file
+    pub first_seen: Option<i64>,
+    pub last_seen: Option<i64>,
+    // the media info parsed from metadata
+    pub media: MediaInfo,
+    // no datetime is found in metadata, the earliest is the file time, see the `review` subcommand
+    #[serde(default)]
+    pub undated: bool,
 }
 
-static DATABASE: OnceLock<Mutex<Connection>> = OnceLock::new();
+// 查询 FileInfo 的字段，顺序与 `row_to_finfo` 一致
+const FINFO_COLUMNS: &str = "parts, hash, algorithm, earliest, size, source, first_seen, last_seen, \
+    mime, make, model, width, height, duration, latitude, longitude, COALESCE(undated, 0)";
 
-pub fn get_connection() -> &'static Mutex<Connection> {
-    DATABASE.get_or_init(|| {
-        let path = config::CONFIG.database.as_ref().unwrap();
-        Mutex::new(db_init(path).unwrap())
-    })
+// the source file index, used to skip hashing the unchanged files
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceIndex {
+    pub path: String,
+    pub size: i64,
+    // the modified time in nanoseconds
+    pub mtime: i64,
+    // the inode and device number, always 0 on non-unix
+    pub inode: i64,
+    pub dev: i64,
+    pub hash: String,
+    pub algorithm: String,
+}
+
+impl SourceIndex {
+    // 获取文件的索引信息，hash 需要另外设置
+    pub fn stat(path: &Path) -> std::io::Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        let mtime = meta
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as i64);
+        #[cfg(unix)]
+        let (inode, dev) = {
+            use std::os::unix::fs::MetadataExt;
+            (meta.ino() as i64, meta.dev() as i64)
+        };
+        #[cfg(not(unix))]
+        let (inode, dev) = (0, 0);
+        Ok(SourceIndex {
+            path: path.to_string_lossy().to_string(),
+            size: meta.len() as i64,
+            mtime,
+            inode,
+            dev,
+            hash: String::new(),
+            algorithm: String::new(),
+        })
+    }
+
+    // 文件的 size, mtime, inode, dev 都没有变化，则认为文件没有变化, hash 算法变化时需要重新计算
+    pub fn unchanged(&self, other: &SourceIndex) -> bool {
+        self.path == other.path
+            && self.algorithm == other.algorithm
+            && self.size == other.size
+            && self.mtime == other.mtime
+            && self.inode == other.inode
+            && self.dev == other.dev
+    }
 }
 
-pub fn db_init(p: &Path) -> Result<Connection> {
-    if p.is_file() {
+// a run of `place`, the id is used to track what the run did
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunInfo {
+    pub id: i64,
+    // the start timestamp
+    pub started: i64,
+}
+
+// the full record of a run, see the `history` subcommand
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RunRecord {
+    pub id: i64,
+    pub started: i64,
+    // the end timestamp, None if the run is still running or interrupted
+    pub ended: Option<i64>,
+    // the json of input roots or listed files
+    pub input: Option<String>,
+    pub output: Option<String>,
+    // the json of command flags
+    pub flags: Option<String>,
+    // the binary version
+    pub version: Option<String>,
+    // the json of processed counts
+    pub counts: Option<String>,
+    // the timestamp when the run was undone
+    pub undone: Option<i64>,
+}
+
+// a change made by a run to the archive or the data table
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ActionInfo {
+    pub run_id: i64,
+    pub hash: String,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    // copy, delete, conflict-<policy> the archived file, insert, update the data record
+    pub action: String,
+    pub timestamp: i64,
+    // the path where the deleted or conflict archived file was moved to
+    pub trash: Option<String>,
+    // the json of the data record before updated
+    pub previous: Option<String>,
+}
+
+// a source file seen by a run, all the copies of the same hash are recorded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceInfo {
+    pub hash: String,
+    pub path: String,
+    pub size: i64,
+    // the modified time in nanoseconds
+    pub mtime: i64,
+    pub run_id: i64,
+}
+
+// the perceptual hash of an archived file, the image fields are None if it's not an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PerceptualInfo {
+    pub hash: String,
+    pub dhash: Option<i64>,
+    pub phash: Option<i64>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+}
+
+/// the directory in the output to store the database.
+pub const DATA_DIR: &str = ".mmfplace";
+
+// 没有指定输出目录时使用数据目录下的 place.db，程序目录下存在旧版本的数据库时继续使用
+pub(crate) fn default_database() -> PathBuf {
+    let legacy = config::CURRENT_FILE("place.db");
+    if legacy.is_file() {
+        legacy
+    } else {
+        config::data_dir().join("place.db")
+    }
+}
+
+// 复制旧版本的数据库，只保留文件存在于该归档目录中的记录，返回保留的文件数
+fn copy_legacy(legacy: &Path, path: &Path, output: &Path) -> anyhow::Result<usize> {
+    std::fs::copy(legacy, path)?;
+    let mut conn = db_init(path)?;
+    let tx = conn.transaction()?;
+    let mut removed = 0;
+    for finfo in query_finfo_all(&tx)? {
+        let file = finfo
+            .parts
+            .iter()
+            .fold(output.to_path_buf(), |o, p| o.join(p));
+        if !file.is_file() {
+            removed += delete_finfo(&tx, &finfo.hash)?;
+        }
+    }
+    // 其他归档目录的运行记录和操作，以及不再有对应文件的索引
+    tx.execute(
+        "DELETE FROM runs WHERE output IS NOT NULL AND output != ?",
+        [output.to_string_lossy()],
+    )?;
+    tx.execute(
+        "DELETE FROM actions WHERE run_id NOT IN (SELECT id FROM runs)",
+        [],
+    )?;
+    tx.execute(
+        "DELETE FROM source_index WHERE hash NOT IN (SELECT hash FROM data)",
+        [],
+    )?;
+    tx.execute(
+        "DELETE FROM overrides WHERE hash NOT IN (SELECT hash FROM data)",
+        [],
+    )?;
+    tx.execute("DELETE FROM meta WHERE key = 'output'", [])?;
+    let kept: usize = tx.query_row("SELECT COUNT(*) FROM data", [], |row| row.get(0))?;
+    tx.commit()?;
+    debug!(from=?legacy, to=?path, kept, removed, "copy the legacy database");
+    Ok(kept)
+}
+
+// 默认使用归档目录下的 `.mmfplace/place.db`，不存在时从程序目录下的旧数据库复制该归档目录的记录
+pub(crate) fn output_database(output: &Path) -> anyhow::Result<PathBuf> {
+    let path = output.join(DATA_DIR).join("place.db");
+    let legacy = config::CURRENT_FILE("place.db");
+    if !path.exists() && legacy.is_file() {
+        std::fs::create_dir_all(output.join(DATA_DIR))?;
+        match copy_legacy(&legacy, &path, output) {
+            Ok(0) => std::fs::remove_file(&path)?,
+            Ok(kept) => {
+                warn!(from=?legacy, to=?path, kept, "copy the records of the output from the legacy database, it can be removed if not shared with other outputs");
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        }
+    }
+    Ok(path)
+}
+
+// 记录数据库所属的归档目录，与之前记录的不一致时警告并返回 false
+pub(crate) fn check_output(
+    conn: &Connection,
+    database: &Path,
+    output: &Path,
+) -> anyhow::Result<bool> {
+    let recorded = query_meta(conn, "output")?;
+    let current = output.to_string_lossy();
+    match recorded {
+        None => {
+            set_meta(conn, "output", &current)?;
+        }
+        Some(recorded) if recorded != current => {
+            warn!(database=?database, recorded, output=%current, "⚠️ the database was created for another output, the dealt files may be placed by the recorded paths");
+            return Ok(false);
+        }
+        _ => {}
+    }
+    Ok(true)
+}
+
+/// open the database, the directory is created and the tables are migrated.
+pub fn open_database(path: &Path) -> anyhow::Result<Connection> {
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    db_init(path)
+}
+
+pub fn db_init(p: &Path) -> anyhow::Result<Connection> {
+    let exists = p.is_file();
+    if exists {
         info!(file=?p, "Loading Database exists and using it");
     } else {
         warn!(file=?p, "Loading Database not found, creating a new one");
     }
     let conn = Connection::open(p)?;
-    // 创建表（如果不存在）
+    migrate(&conn, p, exists)?;
+    Ok(conn)
+}
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+// 按顺序执行的数据库迁移，只能在末尾追加，已发布的迁移不能修改
+// 引入 schema_version 之前的数据库版本为 0，其中可能已经存在部分表和字段，所以迁移需要可重复执行
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create data table",
+        up: migrate_data,
+    },
+    Migration {
+        version: 2,
+        description: "add hash algorithm to data",
+        up: migrate_data_algorithm,
+    },
+    Migration {
+        version: 3,
+        description: "create source_index table",
+        up: migrate_source_index,
+    },
+    Migration {
+        version: 4,
+        description: "create phash table",
+        up: migrate_phash,
+    },
+    Migration {
+        version: 5,
+        description: "add file details to data",
+        up: migrate_data_details,
+    },
+    Migration {
+        version: 6,
+        description: "create runs and sources table",
+        up: migrate_sources,
+    },
+    Migration {
+        version: 7,
+        description: "add run details and create actions table",
+        up: migrate_actions,
+    },
+    Migration {
+        version: 8,
+        description: "add undo details to runs and actions",
+        up: migrate_undo,
+    },
+    Migration {
+        version: 9,
+        description: "create meta table",
+        up: migrate_meta,
+    },
+    Migration {
+        version: 10,
+        description: "add undated to data and create overrides table",
+        up: migrate_overrides,
+    },
+    Migration {
+        version: 11,
+        description: "add reverted to runs",
+        up: migrate_reverted,
+    },
+];
+
+/// the database schema version supported by this binary.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+fn migrate_data(conn: &Connection) -> Result<()> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS data (
             id INTEGER PRIMARY KEY,
@@ -50,33 +353,315 @@ pub fn db_init(p: &Path) -> Result<Connection> {
     )?;
     // 创建索引
     conn.execute("CREATE INDEX IF NOT EXISTS idx_hash ON data (hash)", [])?;
-    Ok(conn)
+    Ok(())
+}
+
+fn migrate_data_algorithm(conn: &Connection) -> Result<()> {
+    // 旧版本数据库没有 algorithm 字段，之前都是使用 md5
+    add_column(conn, "data", "algorithm", "TEXT NOT NULL DEFAULT 'md5'")
+}
+
+fn migrate_source_index(conn: &Connection) -> Result<()> {
+    // 源文件索引，增量扫描时未变化的文件不需要重新计算 hash
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS source_index (
+            path TEXT PRIMARY KEY,
+            size INTEGER NOT NULL,
+            mtime INTEGER NOT NULL,
+            inode INTEGER NOT NULL,
+            dev INTEGER NOT NULL,
+            hash TEXT NOT NULL
+        )",
+        [],
+    )?;
+    add_column(
+        conn,
+        "source_index",
+        "algorithm",
+        "TEXT NOT NULL DEFAULT 'md5'",
+    )
+}
+
+fn migrate_phash(conn: &Connection) -> Result<()> {
+    // 图片的感知 hash，用于查找相似图片
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS phash (
+            hash TEXT PRIMARY KEY,  -- data.hash
+            dhash INTEGER,
+            phash INTEGER,
+            width INTEGER,
+            height INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_data_details(conn: &Connection) -> Result<()> {
+    // 旧记录的这些字段为空
+    for (column, define) in [
+        ("size", "INTEGER"),
+        ("source", "TEXT"),
+        ("first_seen", "INTEGER"),
+        ("last_seen", "INTEGER"),
+        ("mime", "TEXT"),
+        ("make", "TEXT"),
+        ("model", "TEXT"),
+        ("width", "INTEGER"),
+        ("height", "INTEGER"),
+        ("duration", "REAL"),
+        ("latitude", "REAL"),
+        ("longitude", "REAL"),
+    ] {
+        add_column(conn, "data", column, define)?;
+    }
+    Ok(())
+}
+
+fn migrate_sources(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            started INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    // 同一个 hash 的所有源文件路径，用于追溯文件来源
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sources (
+            hash TEXT NOT NULL,
+            path TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            mtime INTEGER NOT NULL,
+            run_id INTEGER NOT NULL,    -- runs.id, the last run which saw it
+            PRIMARY KEY (hash, path)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_sources_path ON sources (path)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_actions(conn: &Connection) -> Result<()> {
+    for (column, define) in [
+        ("ended", "INTEGER"),
+        ("input", "TEXT"),
+        ("output", "TEXT"),
+        ("flags", "TEXT"),
+        ("version", "TEXT"),
+        ("counts", "TEXT"),
+    ] {
+        add_column(conn, "runs", column, define)?;
+    }
+    // 每次运行对归档文件和 data 表的修改记录
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS actions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            run_id INTEGER NOT NULL,    -- runs.id
+            hash TEXT NOT NULL,
+            source TEXT,
+            destination TEXT,
+            action TEXT NOT NULL,
+            timestamp INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_actions_run ON actions (run_id)",
+        [],
+    )?;
+    Ok(())
 }
 
-fn insert(conn: &Connection, parts: &str, hash: &str, earliest: i64) -> Result<usize> {
+fn migrate_undo(conn: &Connection) -> Result<()> {
+    add_column(conn, "runs", "undone", "INTEGER")?;
+    // 撤销时需要恢复被覆盖、删除的归档文件和更新前的记录
+    add_column(conn, "actions", "trash", "TEXT")?;
+    add_column(conn, "actions", "previous", "TEXT")
+}
+
+fn migrate_meta(conn: &Connection) -> Result<()> {
+    // 数据库的元信息，如对应的归档目录
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_overrides(conn: &Connection) -> Result<()> {
+    add_column(conn, "data", "undated", "INTEGER")?;
+    // 手动指定的最早时间，优先于元数据和文件时间
     conn.execute(
-        "INSERT INTO data (parts, hash, earliest) VALUES (?, ?, ?)",
-        rusqlite::params![parts, hash, earliest],
+        "CREATE TABLE IF NOT EXISTS overrides (
+            hash TEXT PRIMARY KEY,
+            earliest INTEGER NOT NULL,  -- the DateTime<Local> timestamp
+            created INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn migrate_reverted(conn: &Connection) -> Result<()> {
+    // 撤销中断时已经倒序还原的操作数，再次撤销时从剩余的操作继续
+    add_column(conn, "runs", "reverted", "INTEGER NOT NULL DEFAULT 0")
+}
+
+pub fn query_meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    conn.query_row("SELECT value FROM meta WHERE key = ?", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+pub fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+        [key, value],
+    )
+}
+
+fn schema_version(conn: &Connection) -> Result<i64> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied INTEGER NOT NULL    -- timestamp
+        )",
+        [],
+    )?;
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
     )
 }
 
+// 备份到数据库同级目录 `place.db.v{version}-{time}.bak`
+fn backup(conn: &Connection, p: &Path, version: i64) -> anyhow::Result<std::path::PathBuf> {
+    let name = p
+        .file_name()
+        .ok_or(anyhow::anyhow!("invalid database path: {:?}", p))?
+        .to_string_lossy();
+    let time = chrono::Local::now().format("%Y%m%d%H%M%S");
+    let path = p.with_file_name(format!("{}.v{}-{}.bak", name, version, time));
+    conn.execute("VACUUM INTO ?", [path.to_string_lossy()])?;
+    Ok(path)
+}
+
+// 执行未应用的迁移，已存在的数据库迁移前先备份，不支持比当前程序更新的数据库
+fn migrate(conn: &Connection, p: &Path, exists: bool) -> anyhow::Result<()> {
+    let version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(anyhow::anyhow!(
+            "the database version {} is newer than the supported version {}, please upgrade mmfplace",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    if exists {
+        let path = backup(conn, p, version)?;
+        info!(file=?path, "backup the database before migrating");
+    }
+    for m in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let tx = conn.unchecked_transaction()?;
+        (m.up)(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_version (version, description, applied) VALUES (?, ?, ?)",
+            rusqlite::params![m.version, m.description, chrono::Local::now().timestamp()],
+        )?;
+        tx.commit()?;
+        info!(
+            version = m.version,
+            description = m.description,
+            "migrate the database"
+        );
+    }
+    Ok(())
+}
+
+// 表中不存在该字段时添加
+fn add_column(conn: &Connection, table: &str, column: &str, define: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<String>>>()?
+        .iter()
+        .any(|c| c == column);
+    if !exists {
+        warn!(table, column, "add the missing column to database");
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, define),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert(
+    conn: &Connection,
+    parts: &str,
+    hash: &str,
+    algorithm: &str,
+    earliest: i64,
+) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO data (parts, hash, algorithm, earliest) VALUES (?, ?, ?, ?)",
+        rusqlite::params![parts, hash, algorithm, earliest],
+    )
+}
+
+fn row_to_finfo<'a>(row: &rusqlite::Row) -> Result<FileInfo<'a, String>> {
+    let parts_json: String = row.get(0)?;
+    let hash: String = row.get(1)?;
+    let algorithm: String = row.get(2)?;
+    let earliest: i64 = row.get(3)?;
+
+    let parts: Vec<String> = serde_json::from_str(&parts_json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    })?;
+
+    Ok(FileInfo {
+        parts: Cow::Owned(parts),
+        hash: Cow::Owned(hash),
+        algorithm: Cow::Owned(algorithm),
+        earliest,
+        size: row.get(4)?,
+        source: row.get::<_, Option<String>>(5)?.map(Cow::Owned),
+        first_seen: row.get(6)?,
+        last_seen: row.get(7)?,
+        media: MediaInfo {
+            mime: row.get(8)?,
+            make: row.get(9)?,
+            model: row.get(10)?,
+            width: row.get(11)?,
+            height: row.get(12)?,
+            duration: row.get(13)?,
+            latitude: row.get(14)?,
+            longitude: row.get(15)?,
+        },
+        undated: row.get(16)?,
+    })
+}
+
 fn query<'a>(conn: &Connection, hash: &str) -> Result<Option<FileInfo<'a, String>>> {
-    let mut stmt = conn.prepare("SELECT parts, hash, earliest FROM data WHERE hash = ?")?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM data WHERE hash = ?",
+        FINFO_COLUMNS
+    ))?;
     let mut rows = stmt.query([hash])?;
     if let Some(row) = rows.next()? {
-        let parts_json: String = row.get(0)?;
-        let hash: String = row.get(1)?;
-        let earliest: i64 = row.get(2)?;
-
-        let parts: Vec<String> = serde_json::from_str(&parts_json).map_err(|e| {
-            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
-        })?;
-
-        Ok(Some(FileInfo {
-            parts: Cow::Owned(parts),
-            hash: Cow::Owned(hash),
-            earliest,
-        }))
+        Ok(Some(row_to_finfo(row)?))
     } else {
         Ok(None)
     }
@@ -95,16 +680,48 @@ fn update(conn: &Connection, hash: &str, parts: &str, earliest: i64) -> Result<u
         })
 }
 
+// 更新文件的详细信息，first_seen 只在第一次设置
+fn update_details<T>(conn: &Connection, fh: &FileInfo<T>) -> Result<usize>
+where
+    T: AsRef<str> + Clone + 'static,
+{
+    let m = &fh.media;
+    conn.execute(
+        "UPDATE data SET size = ?, source = ?, first_seen = COALESCE(first_seen, ?), last_seen = ?,
+            mime = ?, make = ?, model = ?, width = ?, height = ?, duration = ?, latitude = ?, longitude = ?,
+            undated = ? WHERE hash = ?",
+        rusqlite::params![
+            fh.size,
+            fh.source,
+            fh.first_seen,
+            fh.last_seen,
+            m.mime,
+            m.make,
+            m.model,
+            m.width,
+            m.height,
+            m.duration,
+            m.latitude,
+            m.longitude,
+            fh.undated,
+            fh.hash
+        ],
+    )
+}
+
 pub fn insert_finfo<T>(conn: &Connection, fh: &FileInfo<T>) -> Result<usize>
 where
     T: AsRef<str> + 'static + Serialize + Clone,
 {
-    insert(
+    let r = insert(
         conn,
         &json!(fh.parts).to_string(),
         fh.hash.as_ref(),
+        fh.algorithm.as_ref(),
         fh.earliest,
-    )
+    )?;
+    update_details(conn, fh)?;
+    Ok(r)
 }
 
 pub fn query_finfo<'a>(conn: &Connection, hash: &str) -> Result<Option<FileInfo<'a, String>>> {
@@ -118,7 +735,338 @@ where
     let parts = serde_json::to_string(&finfo.parts).map_err(|e| {
         rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
     })?;
-    update(conn, &finfo.hash, &parts, finfo.earliest)
+    let r = update(conn, &finfo.hash, &parts, finfo.earliest)?;
+    update_details(conn, finfo)?;
+    Ok(r)
+}
+
+// 归档文件移动后更新路径
+pub fn update_parts(conn: &Connection, hash: &str, parts: &[String]) -> Result<usize> {
+    conn.execute(
+        "UPDATE data SET parts = ? WHERE hash = ?",
+        rusqlite::params![json!(parts).to_string(), hash],
+    )
+}
+
+// 再次遇到已归档的文件时更新 last_seen
+pub fn touch_finfo(conn: &Connection, hash: &str, last_seen: i64) -> Result<usize> {
+    conn.execute(
+        "UPDATE data SET last_seen = ? WHERE hash = ?",
+        rusqlite::params![last_seen, hash],
+    )
+}
+
+// 获取所有不是使用指定算法计算 hash 的记录
+pub fn query_finfo_not_algorithm<'a>(
+    conn: &Connection,
+    algorithm: &str,
+) -> Result<Vec<FileInfo<'a, String>>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM data WHERE algorithm != ?",
+        FINFO_COLUMNS
+    ))?;
+    stmt.query_map([algorithm], row_to_finfo)?.collect()
+}
+
+// 使用新的算法重新计算 hash 后更新
+pub fn update_hash(
+    conn: &Connection,
+    old_hash: &str,
+    new_hash: &str,
+    algorithm: &str,
+) -> Result<usize> {
+    for table in ["phash", "sources", "actions", "overrides"] {
+        conn.execute(
+            &format!("UPDATE {} SET hash = ? WHERE hash = ?", table),
+            rusqlite::params![new_hash, old_hash],
+        )?;
+    }
+    conn.execute(
+        "UPDATE data SET hash = ?, algorithm = ? WHERE hash = ?",
+        rusqlite::params![new_hash, algorithm, old_hash],
+    )
+}
+
+// 没有从元数据中解析出时间的记录，即待确认的队列
+pub fn query_finfo_undated<'a>(conn: &Connection) -> Result<Vec<FileInfo<'a, String>>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM data WHERE undated = 1 ORDER BY parts",
+        FINFO_COLUMNS
+    ))?;
+    stmt.query_map([], row_to_finfo)?.collect()
+}
+
+// 手动指定时间后移动到新路径
+pub fn update_earliest(
+    conn: &Connection,
+    hash: &str,
+    parts: &[String],
+    earliest: i64,
+    undated: bool,
+) -> Result<usize> {
+    conn.execute(
+        "UPDATE data SET parts = ?, earliest = ?, undated = ? WHERE hash = ?",
+        rusqlite::params![json!(parts).to_string(), earliest, undated, hash],
+    )
+}
+
+pub fn query_override(conn: &Connection, hash: &str) -> Result<Option<i64>> {
+    conn.query_row(
+        "SELECT earliest FROM overrides WHERE hash = ?",
+        [hash],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn upsert_override(conn: &Connection, hash: &str, earliest: i64) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO overrides (hash, earliest, created) VALUES (?, ?, ?)",
+        rusqlite::params![hash, earliest, chrono::Local::now().timestamp()],
+    )
+}
+
+pub fn delete_override(conn: &Connection, hash: &str) -> Result<usize> {
+    conn.execute("DELETE FROM overrides WHERE hash = ?", [hash])
+}
+
+pub fn query_finfo_all<'a>(conn: &Connection) -> Result<Vec<FileInfo<'a, String>>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM data", FINFO_COLUMNS))?;
+    stmt.query_map([], row_to_finfo)?.collect()
+}
+
+pub fn delete_finfo(conn: &Connection, hash: &str) -> Result<usize> {
+    conn.execute("DELETE FROM phash WHERE hash = ?", [hash])?;
+    conn.execute("DELETE FROM sources WHERE hash = ?", [hash])?;
+    conn.execute("DELETE FROM data WHERE hash = ?", [hash])
+}
+
+pub fn insert_run(conn: &Connection, started: i64) -> Result<RunInfo> {
+    conn.execute("INSERT INTO runs (started) VALUES (?)", [started])?;
+    Ok(RunInfo {
+        id: conn.last_insert_rowid(),
+        started,
+    })
+}
+
+pub fn update_run(conn: &Connection, run: &RunRecord) -> Result<usize> {
+    conn.execute(
+        "UPDATE runs SET ended = ?, input = ?, output = ?, flags = ?, version = ?, counts = ?
+            WHERE id = ?",
+        rusqlite::params![
+            run.ended,
+            run.input,
+            run.output,
+            run.flags,
+            run.version,
+            run.counts,
+            run.id
+        ],
+    )
+}
+
+// 维护命令结束时记录运行的输入、参数和计数，均为 json
+pub fn finish_run(
+    conn: &Connection,
+    run: RunInfo,
+    output: &Path,
+    input: serde_json::Value,
+    flags: Option<serde_json::Value>,
+    counts: serde_json::Value,
+) -> Result<usize> {
+    let record = RunRecord {
+        id: run.id,
+        started: run.started,
+        ended: Some(chrono::Local::now().timestamp()),
+        input: Some(input.to_string()),
+        output: Some(output.to_string_lossy().to_string()),
+        flags: flags.map(|f| f.to_string()),
+        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        counts: Some(counts.to_string()),
+        ..Default::default()
+    };
+    update_run(conn, &record)
+}
+
+pub fn query_runs(conn: &Connection) -> Result<Vec<RunRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, started, ended, input, output, flags, version, counts, undone FROM runs
+            ORDER BY id",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(RunRecord {
+            id: row.get(0)?,
+            started: row.get(1)?,
+            ended: row.get(2)?,
+            input: row.get(3)?,
+            output: row.get(4)?,
+            flags: row.get(5)?,
+            version: row.get(6)?,
+            counts: row.get(7)?,
+            undone: row.get(8)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn insert_action(conn: &Connection, action: &ActionInfo) -> Result<usize> {
+    conn.execute(
+        "INSERT INTO actions (run_id, hash, source, destination, action, timestamp, trash, previous)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            action.run_id,
+            action.hash,
+            action.source,
+            action.destination,
+            action.action,
+            action.timestamp,
+            action.trash,
+            action.previous
+        ],
+    )
+}
+
+fn row_to_action(row: &rusqlite::Row) -> Result<ActionInfo> {
+    Ok(ActionInfo {
+        run_id: row.get(0)?,
+        hash: row.get(1)?,
+        source: row.get(2)?,
+        destination: row.get(3)?,
+        action: row.get(4)?,
+        timestamp: row.get(5)?,
+        trash: row.get(6)?,
+        previous: row.get(7)?,
+    })
+}
+
+pub fn query_actions(conn: &Connection, run_id: i64) -> Result<Vec<ActionInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT run_id, hash, source, destination, action, timestamp, trash, previous FROM actions
+            WHERE run_id = ? ORDER BY id",
+    )?;
+    stmt.query_map([run_id], row_to_action)?.collect()
+}
+
+// 指定运行之后的所有未撤销的运行的修改
+pub fn query_actions_after(conn: &Connection, run_id: i64) -> Result<Vec<ActionInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT run_id, hash, source, destination, action, timestamp, trash, previous FROM actions
+            WHERE run_id > ? AND run_id IN (SELECT id FROM runs WHERE undone IS NULL) ORDER BY id",
+    )?;
+    stmt.query_map([run_id], row_to_action)?.collect()
+}
+
+pub fn set_run_undone(conn: &Connection, run_id: i64, undone: i64) -> Result<usize> {
+    conn.execute(
+        "UPDATE runs SET undone = ? WHERE id = ?",
+        rusqlite::params![undone, run_id],
+    )
+}
+
+pub fn query_run_reverted(conn: &Connection, run_id: i64) -> Result<usize> {
+    conn.query_row("SELECT reverted FROM runs WHERE id = ?", [run_id], |row| {
+        row.get(0)
+    })
+}
+
+pub fn set_run_reverted(conn: &Connection, run_id: i64, reverted: usize) -> Result<usize> {
+    conn.execute(
+        "UPDATE runs SET reverted = ? WHERE id = ?",
+        rusqlite::params![reverted, run_id],
+    )
+}
+
+pub fn upsert_source(conn: &Connection, source: &SourceInfo) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sources (hash, path, size, mtime, run_id) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![
+            source.hash,
+            source.path,
+            source.size,
+            source.mtime,
+            source.run_id
+        ],
+    )
+}
+
+pub fn query_sources(conn: &Connection, hash: &str) -> Result<Vec<SourceInfo>> {
+    let mut stmt = conn.prepare(
+        "SELECT hash, path, size, mtime, run_id FROM sources WHERE hash = ? ORDER BY path",
+    )?;
+    stmt.query_map([hash], |row| {
+        Ok(SourceInfo {
+            hash: row.get(0)?,
+            path: row.get(1)?,
+            size: row.get(2)?,
+            mtime: row.get(3)?,
+            run_id: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn query_phash(conn: &Connection, hash: &str) -> Result<Option<PerceptualInfo>> {
+    let mut stmt =
+        conn.prepare("SELECT hash, dhash, phash, width, height FROM phash WHERE hash = ?")?;
+    let mut rows = stmt.query([hash])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(PerceptualInfo {
+            hash: row.get(0)?,
+            dhash: row.get(1)?,
+            phash: row.get(2)?,
+            width: row.get(3)?,
+            height: row.get(4)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn upsert_phash(conn: &Connection, info: &PerceptualInfo) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO phash (hash, dhash, phash, width, height) VALUES (?, ?, ?, ?, ?)",
+        rusqlite::params![info.hash, info.dhash, info.phash, info.width, info.height],
+    )
+}
+
+// 源文件索引只是缓存，算法变化后直接删除即可
+pub fn delete_index_not_algorithm(conn: &Connection, algorithm: &str) -> Result<usize> {
+    conn.execute("DELETE FROM source_index WHERE algorithm != ?", [algorithm])
+}
+
+pub fn query_index(conn: &Connection, path: &str) -> Result<Option<SourceIndex>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, size, mtime, inode, dev, hash, algorithm FROM source_index WHERE path = ?",
+    )?;
+    let mut rows = stmt.query([path])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(SourceIndex {
+            path: row.get(0)?,
+            size: row.get(1)?,
+            mtime: row.get(2)?,
+            inode: row.get(3)?,
+            dev: row.get(4)?,
+            hash: row.get(5)?,
+            algorithm: row.get(6)?,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn upsert_index(conn: &Connection, index: &SourceIndex) -> Result<usize> {
+    conn.execute(
+        "INSERT OR REPLACE INTO source_index (path, size, mtime, inode, dev, hash, algorithm) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        rusqlite::params![
+            index.path,
+            index.size,
+            index.mtime,
+            index.inode,
+            index.dev,
+            index.hash,
+            index.algorithm
+        ],
+    )
 }
 
 // test
@@ -167,13 +1115,13 @@ mod tests {
             println!("conn: {:#?}", conn);
 
             for (parts, hash, timestamp) in data.iter() {
-                let r = insert(&conn, &parts, &hash, *timestamp as i64);
+                let r = insert(&conn, parts, hash, "md5", *timestamp as i64);
                 println!("insert: {:#?}", r);
                 assert!(r.is_ok());
                 assert!(r.unwrap() == 1);
             }
             for (parts, hash, timestamp) in data.iter() {
-                let r = insert(&conn, &parts, &hash, *timestamp as i64);
+                let r = insert(&conn, parts, hash, "md5", *timestamp as i64);
                 println!("insert: {:#?}", r);
                 assert!(r.is_err());
                 assert!(
@@ -195,25 +1143,29 @@ mod tests {
             FileInfo {
                 parts: Cow::Borrowed(&parts1),
                 hash: Cow::Borrowed("hash1"),
+                algorithm: Cow::Borrowed("md5"),
                 earliest: 0,
+                ..Default::default()
             },
             FileInfo {
                 parts: Cow::Borrowed(&parts2),
                 hash: Cow::Borrowed("hash2"),
+                algorithm: Cow::Borrowed("md5"),
                 earliest: 0,
+                ..Default::default()
             },
         ];
         let p = get_db_path("test_insert_finfo.db");
         {
             let conn = db_init(&p).unwrap();
             for test in tests.iter() {
-                let r = insert_finfo(&conn, &test);
+                let r = insert_finfo(&conn, test);
                 println!("insert: {:#?}", r);
                 assert!(r.is_ok());
                 assert!(r.unwrap() == 1);
             }
             for test in tests.iter() {
-                let r = insert_finfo(&conn, &test);
+                let r = insert_finfo(&conn, test);
                 println!("insert: {:#?}", r);
                 assert!(r.is_err());
                 assert!(
@@ -234,7 +1186,9 @@ mod tests {
         let test = FileInfo {
             parts: Cow::Borrowed(&parts),
             hash: Cow::Borrowed("hash1"),
+            algorithm: Cow::Borrowed("md5"),
             earliest: 123,
+            ..Default::default()
         };
         {
             let conn = db_init(&p).unwrap();
@@ -270,8 +1224,10 @@ mod tests {
             let conn = db_init(&p).unwrap();
             let test = FileInfo {
                 parts: Cow::Borrowed(&parts),
-                hash: Cow::Borrowed(&hash),
-                earliest: earliest,
+                hash: Cow::Borrowed(hash),
+                algorithm: Cow::Borrowed("md5"),
+                earliest,
+                ..Default::default()
             };
             let r = insert_finfo(&conn, &test);
             println!("insert: {:#?}", r);
@@ -296,4 +1252,403 @@ mod tests {
 
         std::fs::remove_file(p).unwrap();
     }
+
+    #[test]
+    fn test_source_index() {
+        let p = get_db_path("test_source_index.db");
+        {
+            let conn = db_init(&p).unwrap();
+            let file = Path::new("Cargo.toml");
+            let mut index = SourceIndex::stat(file).unwrap();
+            index.hash = "hash1".to_string();
+            assert!(query_index(&conn, &index.path).unwrap().is_none());
+            assert!(upsert_index(&conn, &index).unwrap() == 1);
+
+            let r = query_index(&conn, &index.path).unwrap().unwrap();
+            assert_eq!(r, index);
+            assert!(r.unchanged(&SourceIndex::stat(file).unwrap()));
+
+            index.size += 1;
+            index.hash = "hash2".to_string();
+            assert!(upsert_index(&conn, &index).unwrap() == 1);
+            let r = query_index(&conn, &index.path).unwrap().unwrap();
+            assert!(r.hash == "hash2");
+            assert!(!r.unchanged(&SourceIndex::stat(file).unwrap()));
+
+            // the hash algorithm changed
+            let mut index = SourceIndex::stat(file).unwrap();
+            index.algorithm = "xxh3".to_string();
+            assert!(!SourceIndex::stat(file).unwrap().unchanged(&index));
+        }
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    // 删除迁移时生成的备份文件
+    fn remove_backups(p: &Path) -> usize {
+        let prefix = format!("{}.v", p.file_name().unwrap().to_string_lossy());
+        std::fs::read_dir(".")
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|e| std::fs::remove_file(e.path()).unwrap())
+            .count()
+    }
+
+    #[test]
+    fn test_add_algorithm_column() {
+        let p = get_db_path("test_add_algorithm_column.db");
+        {
+            // the database created by old version without algorithm column
+            let conn = Connection::open(&p).unwrap();
+            conn.execute(
+                "CREATE TABLE data (
+                    id INTEGER PRIMARY KEY,
+                    parts TEXT NOT NULL,
+                    earliest INTEGER NOT NULL,
+                    hash TEXT NOT NULL UNIQUE
+                )",
+                [],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO data (parts, hash, earliest) VALUES (?, ?, ?)",
+                rusqlite::params![json!(["path", "to", "file1"]).to_string(), "hash1", 123],
+            )
+            .unwrap();
+        }
+        {
+            let conn = db_init(&p).unwrap();
+            let r = query_finfo(&conn, "hash1").unwrap().unwrap();
+            assert!(r.algorithm == "md5");
+            assert!(query_finfo_not_algorithm(&conn, "md5").unwrap().is_empty());
+            assert!(query_finfo_not_algorithm(&conn, "xxh3").unwrap().len() == 1);
+
+            assert!(update_hash(&conn, "hash1", "hash2", "xxh3").unwrap() == 1);
+            let r = query_finfo(&conn, "hash2").unwrap().unwrap();
+            assert!(r.algorithm == "xxh3");
+            assert!(r.earliest == 123);
+        }
+
+        std::fs::remove_file(&p).unwrap();
+        assert_eq!(remove_backups(&p), 1);
+    }
+
+    #[test]
+    fn test_phash() {
+        let p = get_db_path("test_phash.db");
+        {
+            let conn = db_init(&p).unwrap();
+            let info = PerceptualInfo {
+                hash: "hash1".to_string(),
+                dhash: Some(-1),
+                phash: Some(i64::MAX),
+                width: Some(640),
+                height: Some(480),
+            };
+            assert!(query_phash(&conn, "hash1").unwrap().is_none());
+            assert!(upsert_phash(&conn, &info).unwrap() == 1);
+            assert_eq!(query_phash(&conn, "hash1").unwrap().unwrap(), info);
+
+            // not an image
+            let info = PerceptualInfo {
+                hash: "hash2".to_string(),
+                ..Default::default()
+            };
+            assert!(upsert_phash(&conn, &info).unwrap() == 1);
+            assert_eq!(query_phash(&conn, "hash2").unwrap().unwrap(), info);
+        }
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        let p = get_db_path("test_migrate.db");
+        {
+            let conn = db_init(&p).unwrap();
+            assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        }
+        // the new database is not backed up, and the migrated one is not migrated again
+        db_init(&p).unwrap();
+        assert_eq!(remove_backups(&p), 0);
+        {
+            // the database created by a newer version
+            let conn = Connection::open(&p).unwrap();
+            conn.execute(
+                "INSERT INTO schema_version (version, description, applied) VALUES (?, ?, ?)",
+                rusqlite::params![SCHEMA_VERSION + 1, "newer", 0],
+            )
+            .unwrap();
+        }
+        let r = db_init(&p);
+        assert!(r.unwrap_err().to_string().contains("newer"));
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_finfo_details() {
+        let p = get_db_path("test_finfo_details.db");
+        let parts = vec!["2002", "06", "withiptcexifgps.jpg"];
+        let mut test = FileInfo {
+            parts: Cow::Borrowed(&parts),
+            hash: Cow::Borrowed("hash1"),
+            algorithm: Cow::Borrowed("md5"),
+            earliest: 123,
+            size: Some(44606),
+            source: Some(Cow::Borrowed("/from/a.jpg")),
+            first_seen: Some(1),
+            last_seen: Some(1),
+            media: MediaInfo {
+                mime: Some("image/jpeg".to_string()),
+                make: Some("FUJIFILM".to_string()),
+                width: Some(600),
+                height: Some(400),
+                latitude: Some(54.9897),
+                longitude: Some(-1.9142),
+                ..Default::default()
+            },
+            undated: true,
+        };
+        {
+            let conn = db_init(&p).unwrap();
+            assert!(insert_finfo(&conn, &test).unwrap() == 1);
+            let r = query_finfo(&conn, "hash1").unwrap().unwrap();
+            assert_eq!(r.size, Some(44606));
+            assert_eq!(r.source.as_deref(), Some("/from/a.jpg"));
+            assert_eq!(r.media, test.media);
+            assert!(r.undated);
+            assert_eq!(query_finfo_undated(&conn).unwrap().len(), 1);
+
+            // the date assigned by hand is kept as an override
+            assert_eq!(query_override(&conn, "hash1").unwrap(), None);
+            upsert_override(&conn, "hash1", 100).unwrap();
+            upsert_override(&conn, "hash1", 99).unwrap();
+            assert_eq!(query_override(&conn, "hash1").unwrap(), Some(99));
+            assert!(
+                update_earliest(&conn, "hash1", &["2002".to_string()], 99, false).unwrap() == 1
+            );
+            assert!(query_finfo_undated(&conn).unwrap().is_empty());
+            delete_override(&conn, "hash1").unwrap();
+            assert_eq!(query_override(&conn, "hash1").unwrap(), None);
+            update_earliest(
+                &conn,
+                "hash1",
+                &test.parts.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+                123,
+                true,
+            )
+            .unwrap();
+
+            assert!(touch_finfo(&conn, "hash1", 2).unwrap() == 1);
+            let r = query_finfo(&conn, "hash1").unwrap().unwrap();
+            assert_eq!((r.first_seen, r.last_seen), (Some(1), Some(2)));
+
+            // the first_seen is kept when updated
+            test.source = Some(Cow::Borrowed("/from/b.jpg"));
+            test.first_seen = Some(3);
+            test.last_seen = Some(3);
+            assert!(update_finfo(&conn, &test).unwrap() == 1);
+            let r = query_finfo(&conn, "hash1").unwrap().unwrap();
+            assert_eq!(r.source.as_deref(), Some("/from/b.jpg"));
+            assert_eq!((r.first_seen, r.last_seen), (Some(1), Some(3)));
+        }
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_sources() {
+        let p = get_db_path("test_sources.db");
+        {
+            let conn = db_init(&p).unwrap();
+            let run = insert_run(&conn, 100).unwrap();
+            assert_eq!(
+                run,
+                RunInfo {
+                    id: 1,
+                    started: 100
+                }
+            );
+            assert_eq!(insert_run(&conn, 200).unwrap().id, 2);
+
+            let mut source = SourceInfo {
+                hash: "hash1".to_string(),
+                path: "/from/b.jpg".to_string(),
+                size: 1,
+                mtime: 2,
+                run_id: 1,
+            };
+            assert!(upsert_source(&conn, &source).unwrap() == 1);
+            source.path = "/from/a.jpg".to_string();
+            assert!(upsert_source(&conn, &source).unwrap() == 1);
+            // seen again by another run
+            source.run_id = 2;
+            assert!(upsert_source(&conn, &source).unwrap() == 1);
+
+            let sources = query_sources(&conn, "hash1").unwrap();
+            assert_eq!(sources.len(), 2);
+            assert_eq!(sources[0], source);
+            assert_eq!(sources[1].run_id, 1);
+
+            assert!(update_hash(&conn, "hash1", "hash2", "xxh3").unwrap() == 0);
+            assert!(query_sources(&conn, "hash1").unwrap().is_empty());
+            assert_eq!(query_sources(&conn, "hash2").unwrap().len(), 2);
+            delete_finfo(&conn, "hash2").unwrap();
+            assert!(query_sources(&conn, "hash2").unwrap().is_empty());
+        }
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_runs_actions() {
+        let p = get_db_path("test_runs_actions.db");
+        {
+            let conn = db_init(&p).unwrap();
+            let run = insert_run(&conn, 100).unwrap();
+            let mut record = RunRecord {
+                id: run.id,
+                started: run.started,
+                input: Some(json!(["/from"]).to_string()),
+                output: Some("/to".to_string()),
+                version: Some("0.3.0".to_string()),
+                ..Default::default()
+            };
+            assert!(update_run(&conn, &record).unwrap() == 1);
+            assert_eq!(query_runs(&conn).unwrap(), vec![record.clone()]);
+            record.ended = Some(200);
+            record.counts = Some(json!({"total": 1}).to_string());
+            assert!(update_run(&conn, &record).unwrap() == 1);
+            assert_eq!(query_runs(&conn).unwrap(), vec![record.clone()]);
+
+            let action = ActionInfo {
+                run_id: run.id,
+                hash: "hash1".to_string(),
+                source: Some("/from/a.jpg".to_string()),
+                destination: Some("/to/2002/11/a.jpg".to_string()),
+                action: "copy".to_string(),
+                timestamp: 150,
+                ..Default::default()
+            };
+            assert!(insert_action(&conn, &action).unwrap() == 1);
+            assert_eq!(query_actions(&conn, run.id).unwrap(), vec![action.clone()]);
+            assert!(query_actions(&conn, run.id + 1).unwrap().is_empty());
+
+            let later = insert_run(&conn, 300).unwrap();
+            let overwrite = ActionInfo {
+                run_id: later.id,
+                action: "overwrite".to_string(),
+                trash: Some("/to/.mmfplace-trash/2/2002/11/a.jpg".to_string()),
+                ..action
+            };
+            assert!(insert_action(&conn, &overwrite).unwrap() == 1);
+            assert_eq!(query_actions_after(&conn, run.id).unwrap(), vec![overwrite]);
+            assert!(query_actions_after(&conn, later.id).unwrap().is_empty());
+
+            assert_eq!(query_run_reverted(&conn, run.id).unwrap(), 0);
+            assert!(set_run_reverted(&conn, run.id, 1).unwrap() == 1);
+            assert_eq!(query_run_reverted(&conn, run.id).unwrap(), 1);
+            assert!(set_run_undone(&conn, run.id, 400).unwrap() == 1);
+            record.undone = Some(400);
+            assert_eq!(query_runs(&conn).unwrap()[0], record);
+            // the undone runs are ignored
+            assert!(set_run_undone(&conn, later.id, 500).unwrap() == 1);
+            assert!(query_actions_after(&conn, run.id).unwrap().is_empty());
+        }
+
+        std::fs::remove_file(p).unwrap();
+    }
+
+    #[test]
+    fn test_copy_legacy() {
+        let output = PathBuf::from("test_copy_legacy");
+        if output.exists() {
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+        std::fs::create_dir_all(output.join("2002")).unwrap();
+        std::fs::write(output.join("2002").join("a.jpg"), b"a").unwrap();
+        let legacy = get_db_path("test_copy_legacy.db");
+        {
+            let conn = db_init(&legacy).unwrap();
+            // the file of hash1 is in the output, hash2 belongs to another output
+            for (hash, parts) in [
+                ("hash1", vec!["2002", "a.jpg"]),
+                ("hash2", vec!["2003", "b.jpg"]),
+            ] {
+                let finfo = FileInfo {
+                    parts: Cow::Owned(parts.iter().map(|p| p.to_string()).collect()),
+                    hash: Cow::Borrowed(hash),
+                    algorithm: Cow::Borrowed("md5"),
+                    ..Default::default()
+                };
+                insert_finfo(&conn, &finfo).unwrap();
+                upsert_override(&conn, hash, 100).unwrap();
+            }
+            for output in [output.to_string_lossy().to_string(), "/other".to_string()] {
+                let run = insert_run(&conn, 100).unwrap();
+                let record = RunRecord {
+                    id: run.id,
+                    started: run.started,
+                    output: Some(output),
+                    ..Default::default()
+                };
+                update_run(&conn, &record).unwrap();
+                let action = ActionInfo {
+                    run_id: run.id,
+                    hash: "hash1".to_string(),
+                    action: "copy".to_string(),
+                    ..Default::default()
+                };
+                insert_action(&conn, &action).unwrap();
+            }
+            set_meta(&conn, "output", "/other").unwrap();
+        }
+
+        let path = output.join("place.db");
+        assert_eq!(copy_legacy(&legacy, &path, &output).unwrap(), 1);
+        {
+            let conn = db_init(&path).unwrap();
+            let hashes = query_finfo_all(&conn)
+                .unwrap()
+                .into_iter()
+                .map(|f| f.hash.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(hashes, vec!["hash1"]);
+            assert_eq!(query_override(&conn, "hash1").unwrap(), Some(100));
+            assert_eq!(query_override(&conn, "hash2").unwrap(), None);
+            let runs = query_runs(&conn).unwrap();
+            assert_eq!(runs.len(), 1);
+            assert_eq!(query_actions_after(&conn, 0).unwrap().len(), 1);
+            assert_eq!(query_meta(&conn, "output").unwrap(), None);
+        }
+        // the legacy database is not changed
+        {
+            let conn = db_init(&legacy).unwrap();
+            assert_eq!(query_finfo_all(&conn).unwrap().len(), 2);
+        }
+
+        std::fs::remove_dir_all(&output).unwrap();
+        std::fs::remove_file(legacy).unwrap();
+    }
+
+    #[test]
+    fn test_check_output() {
+        let conn = db_init(Path::new(":memory:")).unwrap();
+        let database = Path::new("place.db");
+        // the first output is recorded
+        assert!(check_output(&conn, database, Path::new("/to")).unwrap());
+        assert_eq!(
+            query_meta(&conn, "output").unwrap(),
+            Some("/to".to_string())
+        );
+        assert!(check_output(&conn, database, Path::new("/to")).unwrap());
+        // another output is warned, and the recorded one is kept
+        assert!(!check_output(&conn, database, Path::new("/other")).unwrap());
+        assert_eq!(
+            query_meta(&conn, "output").unwrap(),
+            Some("/to".to_string())
+        );
+    }
 }
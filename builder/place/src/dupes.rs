@@ -0,0 +1,293 @@
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+use super::context::Context;
+use super::db::{PerceptualInfo, query_finfo_all, query_phash, upsert_phash};
+use super::phash::{image_hash, similarity};
+use super::target::OUTPUT_GEN;
+
+/// which member of a near-duplicate cluster to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum KeepPolicy {
+    /// keep the highest resolution, then the earliest dated
+    #[default]
+    Resolution,
+    /// keep the earliest dated, then the highest resolution
+    Earliest,
+}
+
+#[derive(Debug, Clone)]
+struct Member {
+    file: PathBuf,
+    dhash: u64,
+    phash: u64,
+    width: u32,
+    height: u32,
+    earliest: i64,
+}
+
+/// an image in a near-duplicate cluster, see `DupesReport`.
+#[derive(Debug, Clone, Default)]
+pub struct DupeImage {
+    /// the relative path in the archive
+    pub file: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub earliest: i64,
+}
+
+/// the result of `place::dupes`, the first image of each cluster is the one to keep.
+#[derive(Debug, Clone, Default)]
+pub struct DupesReport {
+    pub clusters: Vec<Vec<DupeImage>>,
+}
+
+impl DupesReport {
+    /// the lines printed by the `dupes` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (i, c) in self.clusters.iter().enumerate() {
+            lines.push(format!("cluster {} ({} files)", i + 1, c.len()));
+            for (j, m) in c.iter().enumerate() {
+                let date = Local
+                    .timestamp_opt(m.earliest, 0)
+                    .single()
+                    .map(|d| d.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                let mark = if j == 0 { "keep" } else { "dupe" };
+                lines.push(format!(
+                    "  {} {:>5}x{:<5} {} {}",
+                    mark,
+                    m.width,
+                    m.height,
+                    date,
+                    m.file.display()
+                ));
+            }
+        }
+        lines
+    }
+}
+
+// dHash 对渐变敏感，pHash 对频域结构敏感，取两者的平均值，单个 hash 偶然接近时不会被误判
+fn member_similarity(a: &Member, b: &Member) -> f64 {
+    (similarity(a.dhash, b.dhash) + similarity(a.phash, b.phash)) / 2.0
+}
+
+// 并查集，按 dHash 和 pHash 的相似度将图片聚类，只返回包含多个成员的簇
+fn cluster(members: &[Member], threshold: f64) -> Vec<Vec<usize>> {
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        // 路径压缩
+        let mut i = i;
+        while parent[i] != root {
+            let next = parent[i];
+            parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    let mut parent: Vec<usize> = (0..members.len()).collect();
+    for i in 0..members.len() {
+        for j in (i + 1)..members.len() {
+            if member_similarity(&members[i], &members[j]) >= threshold {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                if a != b {
+                    parent[b] = a;
+                }
+            }
+        }
+    }
+    let mut clusters: std::collections::BTreeMap<usize, Vec<usize>> = Default::default();
+    for i in 0..members.len() {
+        let root = find(&mut parent, i);
+        clusters.entry(root).or_default().push(i);
+    }
+    clusters.into_values().filter(|c| c.len() > 1).collect()
+}
+
+// 按保留策略排序，第一个为保留的文件
+fn sort_by_policy(members: &[Member], cluster: &mut [usize], keep: KeepPolicy) {
+    let pixels = |m: &Member| m.width as u64 * m.height as u64;
+    cluster.sort_by(|a, b| {
+        let (a, b) = (&members[*a], &members[*b]);
+        let resolution = pixels(b).cmp(&pixels(a));
+        let earliest = a.earliest.cmp(&b.earliest);
+        match keep {
+            KeepPolicy::Resolution => resolution.then(earliest),
+            KeepPolicy::Earliest => earliest.then(resolution),
+        }
+        .then(a.file.cmp(&b.file))
+    });
+}
+
+// 计算归档文件的感知 hash 并缓存到数据库，非图片文件也会记录，避免重复解码
+async fn fill_phash(ctx: &Context, output: &PathBuf) -> Result<Vec<Member>> {
+    let records = {
+        let conn = ctx.conn()?.lock().unwrap();
+        query_finfo_all(&conn)?
+    };
+    let total = records.len();
+    info!(total, output=?output, "start collect perceptual hash");
+
+    let semaphore = Arc::new(Semaphore::new(ctx.batch()));
+    let mut handles = Vec::new();
+    for finfo in records {
+        let file = OUTPUT_GEN(output, &finfo.parts);
+        let cached = {
+            let conn = ctx.conn()?.lock().unwrap();
+            query_phash(&conn, &finfo.hash)?
+        };
+        let permit = semaphore.clone().acquire_owned().await?;
+        let ctx = ctx.clone();
+        let hash = finfo.hash.into_owned();
+        let earliest = finfo.earliest;
+        handles.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let info = match cached {
+                Some(info) => info,
+                None if !file.is_file() => {
+                    warn!(file=?file, "⚠️ the archived file not found, skip it");
+                    return Ok(None);
+                }
+                None => {
+                    let info = match image_hash(&file)? {
+                        Some(h) => PerceptualInfo {
+                            hash,
+                            // 按位存储为 i64
+                            dhash: Some(h.dhash as i64),
+                            phash: Some(h.phash as i64),
+                            width: Some(h.width as i64),
+                            height: Some(h.height as i64),
+                        },
+                        None => {
+                            debug!(file=?file, "💡 not an image, skip it");
+                            PerceptualInfo {
+                                hash,
+                                ..Default::default()
+                            }
+                        }
+                    };
+                    let conn = ctx.conn()?.lock().unwrap();
+                    upsert_phash(&conn, &info)?;
+                    info
+                }
+            };
+            Ok::<_, anyhow::Error>(match (info.dhash, info.phash, info.width, info.height) {
+                (Some(dhash), Some(phash), Some(width), Some(height)) => Some(Member {
+                    file,
+                    dhash: dhash as u64,
+                    phash: phash as u64,
+                    width: width as u32,
+                    height: height as u32,
+                    earliest,
+                }),
+                _ => None,
+            })
+        }));
+    }
+
+    let mut members = Vec::new();
+    for handle in handles {
+        if let Some(m) = handle.await?? {
+            members.push(m);
+        }
+    }
+    Ok(members)
+}
+
+// 查找归档中的相似图片并输出，仅报告，不删除文件
+pub async fn do_dupes(
+    ctx: &Context,
+    output: PathBuf,
+    threshold: f64,
+    keep: KeepPolicy,
+) -> Result<DupesReport> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(anyhow::anyhow!(
+            "the threshold must be in [0, 1], got {}",
+            threshold
+        ));
+    }
+    let members = fill_phash(ctx, &output).await?;
+    let mut clusters = cluster(&members, threshold);
+    info!(
+        images = members.len(),
+        clusters = clusters.len(),
+        threshold,
+        "find near-duplicate images done"
+    );
+
+    let mut report = DupesReport::default();
+    for c in clusters.iter_mut() {
+        sort_by_policy(&members, c, keep);
+        let images = c
+            .iter()
+            .map(|idx| {
+                let m = &members[*idx];
+                DupeImage {
+                    file: m
+                        .file
+                        .strip_prefix(&output)
+                        .unwrap_or(&m.file)
+                        .to_path_buf(),
+                    width: m.width,
+                    height: m.height,
+                    earliest: m.earliest,
+                }
+            })
+            .collect();
+        report.clusters.push(images);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, phash: u64, size: u32, earliest: i64) -> Member {
+        Member {
+            file: PathBuf::from(name),
+            dhash: phash,
+            phash,
+            width: size,
+            height: size,
+            earliest,
+        }
+    }
+
+    #[test]
+    fn test_cluster() {
+        let members = vec![
+            member("a.jpg", 0, 100, 3),
+            // 2 bits differ from a
+            member("b.jpg", 0b11, 200, 2),
+            member("c.jpg", u64::MAX, 100, 1),
+            // 3 bits differ from both a and b
+            member("d.jpg", 0b1110, 50, 1),
+            // the same phash as a, but the dhash is totally different
+            Member {
+                dhash: u64::MAX,
+                ..member("e.jpg", 0, 100, 1)
+            },
+        ];
+        let mut clusters = cluster(&members, 0.95);
+        assert_eq!(clusters, vec![vec![0, 1, 3]]);
+        // only a and b are similar enough
+        assert_eq!(cluster(&members, 0.96), vec![vec![0, 1]]);
+
+        sort_by_policy(&members, &mut clusters[0], KeepPolicy::Resolution);
+        assert_eq!(clusters[0], vec![1, 0, 3]);
+        sort_by_policy(&members, &mut clusters[0], KeepPolicy::Earliest);
+        assert_eq!(clusters[0], vec![3, 1, 0]);
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+use super::context::Context;
+use super::db::{SourceIndex, query_finfo, query_index};
+use super::input::{Inputs, WalkErrors};
+use utils::crypto::{get_file_hash, get_file_partial_hash};
+
+// 部分 hash 只读取文件开头的字节数
+const PARTIAL_SIZE: u64 = 4096;
+
+/// the output format of the duplicate groups.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum DupfFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+/// the action applied to the duplicates, the kept file is never touched.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum DupfAction {
+    /// replace the duplicates with hardlinks to the kept file
+    Hardlink,
+    /// delete the duplicates
+    Delete,
+}
+
+/// a group of files with the same content.
+#[derive(Debug, Clone, Serialize)]
+pub struct DupfGroup {
+    pub hash: String,
+    pub algorithm: String,
+    pub size: u64,
+    /// the content is archived in the database
+    pub archived: bool,
+    /// the file in the first specified input
+    pub keep: PathBuf,
+    pub duplicates: Vec<PathBuf>,
+}
+
+/// the result of `place::dupf`, the groups are sorted by the kept file.
+#[derive(Debug, Clone, Default)]
+pub struct DupfReport {
+    pub groups: Vec<DupfGroup>,
+}
+
+impl DupfReport {
+    /// the lines printed by the `dupf` command in the format.
+    pub fn lines(&self, format: DupfFormat) -> Result<Vec<String>> {
+        group_lines(&self.groups, format)
+    }
+
+    /// apply the action to the duplicates, only log them without `yes`.
+    pub fn apply(&self, action: DupfAction, yes: bool) -> Result<()> {
+        apply_action(&self.groups, action, yes)
+    }
+}
+
+// 并发计算 hash，读取失败的文件跳过
+async fn hash_files(
+    ctx: &Context,
+    files: Vec<PathBuf>,
+    limit: Option<u64>,
+) -> Result<Vec<(PathBuf, String)>> {
+    let semaphore = Arc::new(Semaphore::new(ctx.batch()));
+    let mut handles = Vec::new();
+    for file in files {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let algorithm = ctx.hash_algorithm().to_string();
+        handles.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let hash = match limit {
+                Some(limit) => get_file_partial_hash(&algorithm, &file, limit),
+                None => get_file_hash(&algorithm, &file),
+            };
+            match hash {
+                Ok(hash) => Some((file, hash)),
+                Err(e) => {
+                    warn!(file=?file, error=%e, "⚠️ hash file failed, skip it");
+                    None
+                }
+            }
+        }));
+    }
+    let mut hashes = Vec::new();
+    for handle in handles {
+        hashes.extend(handle.await?);
+    }
+    Ok(hashes)
+}
+
+// 按 大小 -> 部分 hash -> 完整 hash 逐步分组，只有可能重复的文件才会进入下一步
+// db 可用时直接使用源文件索引中未变化文件的 hash
+async fn find_groups(ctx: &Context, inputs: &Inputs, db: bool) -> Result<Vec<DupfGroup>> {
+    let errors = WalkErrors::default();
+    let mut sizes: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut total = 0;
+    for file in inputs.files(Some(errors.clone())) {
+        match std::fs::metadata(&file) {
+            // 空文件都是相同的，没有意义
+            Ok(m) if m.len() == 0 => debug!(file=?file, "💡 skip the empty file"),
+            Ok(m) => {
+                total += 1;
+                sizes.entry(m.len()).or_default().push(file)
+            }
+            Err(e) => warn!(file=?file, error=%e, "⚠️ stat file failed, skip it"),
+        }
+    }
+    let errors = errors.lock().unwrap().len();
+    if errors > 0 {
+        warn!(
+            errors,
+            "⚠️ some entries could not be walked, see the warnings above"
+        );
+    }
+    sizes.retain(|_, files| files.len() > 1);
+    info!(
+        total,
+        candidates = sizes.values().map(Vec::len).sum::<usize>(),
+        "grouped by size"
+    );
+
+    let algorithm = ctx.hash_algorithm();
+    let mut full: HashMap<PathBuf, String> = HashMap::new();
+    let (mut partial, mut rest) = (Vec::new(), Vec::new());
+    for (size, files) in sizes.iter() {
+        let mut uncached = Vec::new();
+        for file in files {
+            let cached = if db {
+                let conn = ctx.conn()?.lock().unwrap();
+                let mut index = SourceIndex::stat(file)?;
+                index.algorithm = algorithm.to_string();
+                query_index(&conn, &index.path)?
+                    .filter(|i| i.unchanged(&index))
+                    .map(|i| i.hash)
+            } else {
+                None
+            };
+            match cached {
+                Some(hash) => {
+                    full.insert(file.clone(), hash);
+                }
+                None => uncached.push(file.clone()),
+            }
+        }
+        // 小文件的部分 hash 就是完整 hash，存在已知 hash 的文件时也无法通过部分 hash 排除
+        if *size <= PARTIAL_SIZE || uncached.len() < files.len() {
+            rest.extend(uncached);
+        } else {
+            partial.extend(uncached);
+        }
+    }
+
+    let mut heads: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+    for (file, hash) in hash_files(ctx, partial, Some(PARTIAL_SIZE)).await? {
+        let size = std::fs::metadata(&file)?.len();
+        heads.entry((size, hash)).or_default().push(file);
+    }
+    rest.extend(heads.into_values().filter(|f| f.len() > 1).flatten());
+    info!(
+        cached = full.len(),
+        hashing = rest.len(),
+        "grouped by partial hash"
+    );
+    full.extend(hash_files(ctx, rest, None).await?);
+
+    let mut hashes: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (file, hash) in full {
+        hashes.entry(hash).or_default().push(file);
+    }
+    let roots = match inputs {
+        Inputs::Roots { roots, .. } => roots.as_slice(),
+        Inputs::Files(_) => &[],
+    };
+    let mut groups = Vec::new();
+    for (hash, mut files) in hashes.into_iter().filter(|(_, f)| f.len() > 1) {
+        // 保留最先指定的输入目录中的文件
+        files.sort_by_key(|f| (roots.iter().position(|r| f.starts_with(r)), f.clone()));
+        let archived = db && {
+            let conn = ctx.conn()?.lock().unwrap();
+            query_finfo(&conn, &hash)?.is_some_and(|f| f.algorithm == algorithm)
+        };
+        groups.push(DupfGroup {
+            size: std::fs::metadata(&files[0])?.len(),
+            hash,
+            algorithm: algorithm.to_string(),
+            archived,
+            keep: files.remove(0),
+            duplicates: files,
+        });
+    }
+    groups.sort_by(|a, b| a.keep.cmp(&b.keep));
+    Ok(groups)
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn group_lines(groups: &[DupfGroup], format: DupfFormat) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    match format {
+        DupfFormat::Text => {
+            for (i, g) in groups.iter().enumerate() {
+                lines.push(format!(
+                    "group {} ({} files, {} bytes, {} {}){}",
+                    i + 1,
+                    g.duplicates.len() + 1,
+                    g.size,
+                    g.algorithm,
+                    g.hash,
+                    if g.archived { " archived" } else { "" }
+                ));
+                lines.push(format!("  keep {}", g.keep.display()));
+                for d in g.duplicates.iter() {
+                    lines.push(format!("  dupe {}", d.display()));
+                }
+            }
+        }
+        DupfFormat::Json => lines.push(serde_json::to_string_pretty(groups)?),
+        DupfFormat::Csv => {
+            lines.push("group,hash,size,archived,role,path".to_string());
+            for (i, g) in groups.iter().enumerate() {
+                let roles = std::iter::once(("keep", &g.keep))
+                    .chain(g.duplicates.iter().map(|d| ("dupe", d)));
+                for (role, path) in roles {
+                    lines.push(format!(
+                        "{},{},{},{},{},{}",
+                        i + 1,
+                        g.hash,
+                        g.size,
+                        g.archived,
+                        role,
+                        csv_field(&path.to_string_lossy())
+                    ));
+                }
+            }
+        }
+    }
+    Ok(lines)
+}
+
+// 通过临时文件创建硬链接后替换，避免失败时丢失重复文件
+fn hardlink(keep: &Path, dupe: &Path) -> Result<bool> {
+    #[cfg(unix)]
+    {
+        let (k, d) = (SourceIndex::stat(keep)?, SourceIndex::stat(dupe)?);
+        if (k.inode, k.dev) == (d.inode, d.dev) {
+            return Ok(false);
+        }
+    }
+    let name = dupe
+        .file_name()
+        .ok_or(anyhow::anyhow!("invalid file name: {:?}", dupe))?;
+    let temp = dupe.with_file_name(format!(".{}.mmfplace-link", name.to_string_lossy()));
+    std::fs::hard_link(keep, &temp)?;
+    if let Err(e) = std::fs::rename(&temp, dupe) {
+        std::fs::remove_file(&temp)?;
+        return Err(e.into());
+    }
+    Ok(true)
+}
+
+fn apply_action(groups: &[DupfGroup], action: DupfAction, yes: bool) -> Result<()> {
+    let (mut done, mut failed) = (0, 0);
+    for g in groups {
+        for dupe in g.duplicates.iter() {
+            if !yes {
+                info!(file=?dupe, keep=?g.keep, action=?action, "💡 dry run, use `--yes` to apply");
+                continue;
+            }
+            let r = match action {
+                DupfAction::Delete => std::fs::remove_file(dupe).map(|_| true).map_err(Into::into),
+                DupfAction::Hardlink => hardlink(&g.keep, dupe),
+            };
+            match r {
+                Ok(true) => {
+                    info!(file=?dupe, keep=?g.keep, action=?action, "✅ success");
+                    done += 1;
+                }
+                Ok(false) => debug!(file=?dupe, keep=?g.keep, "💡 already hardlinked, skip it"),
+                Err(e) => {
+                    warn!(file=?dupe, keep=?g.keep, action=?action, error=%e, "⚠️ failed");
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if yes {
+        info!(done, failed, action=?action, "apply action done");
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} duplicates could not be processed",
+            failed
+        ));
+    }
+    Ok(())
+}
+
+// 查找输入目录中内容完全相同的文件，action 由调用者在输出之后执行，见 `DupfReport::apply`
+pub async fn do_dupf(ctx: &Context, inputs: Inputs) -> Result<DupfReport> {
+    let algorithm = ctx.hash_algorithm();
+    if !utils::crypto::is_supported_hash(algorithm) {
+        return Err(anyhow::anyhow!("unsupported hash algorithm: {}", algorithm));
+    }
+    let groups = find_groups(ctx, &inputs, ctx.database_exists()).await?;
+    let duplicates: usize = groups.iter().map(|g| g.duplicates.len()).sum();
+    let reclaimable: u64 = groups
+        .iter()
+        .map(|g| g.size * g.duplicates.len() as u64)
+        .sum();
+    info!(
+        groups = groups.len(),
+        duplicates, reclaimable, "find duplicate files done"
+    );
+    Ok(DupfReport { groups })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::WalkOptions;
+
+    #[tokio::test]
+    async fn test_find_groups() {
+        let root = std::env::temp_dir().join("mmfplace_test_dupf");
+        if root.exists() {
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+        let (first, second) = (root.join("first"), root.join("second"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        let big = vec![1u8; PARTIAL_SIZE as usize * 2];
+        let mut tail = big.clone();
+        *tail.last_mut().unwrap() = 2;
+        std::fs::write(second.join("a.bin"), &big).unwrap();
+        std::fs::write(first.join("z.bin"), &big).unwrap();
+        // same size and same head, but different content
+        std::fs::write(second.join("b.bin"), &tail).unwrap();
+        std::fs::write(first.join("small.txt"), "small").unwrap();
+        std::fs::write(second.join("small.txt"), "small").unwrap();
+        std::fs::write(second.join("other.txt"), "other").unwrap();
+        std::fs::write(first.join("empty"), "").unwrap();
+        std::fs::write(second.join("empty"), "").unwrap();
+
+        let inputs = Inputs::Roots {
+            roots: vec![first.clone(), second.clone()],
+            walk: WalkOptions::default(),
+        };
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let groups = find_groups(&ctx, &inputs, false).await.unwrap();
+        println!("groups: {:#?}", groups);
+        assert_eq!(groups.len(), 2);
+        // the file in the first root is kept
+        assert_eq!(groups[0].keep, first.join("small.txt"));
+        assert_eq!(groups[0].duplicates, vec![second.join("small.txt")]);
+        assert_eq!(groups[1].keep, first.join("z.bin"));
+        assert_eq!(groups[1].duplicates, vec![second.join("a.bin")]);
+
+        apply_action(&groups, DupfAction::Hardlink, false).unwrap();
+        assert!(second.join("a.bin").is_file());
+        apply_action(&groups, DupfAction::Hardlink, true).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |p: &Path| std::fs::metadata(p).unwrap().ino();
+            assert_eq!(ino(&first.join("z.bin")), ino(&second.join("a.bin")));
+        }
+        // already hardlinked
+        apply_action(&groups, DupfAction::Hardlink, true).unwrap();
+        apply_action(&groups, DupfAction::Delete, true).unwrap();
+        assert!(!second.join("a.bin").exists());
+        assert!(!second.join("small.txt").exists());
+        assert!(first.join("z.bin").is_file());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("/a/b.jpg"), "/a/b.jpg");
+        assert_eq!(csv_field("/a/b,\"c\".jpg"), "\"/a/b,\"\"c\"\".jpg\"");
+    }
+}
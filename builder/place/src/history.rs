@@ -0,0 +1,90 @@
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+
+use super::context::Context;
+use super::db::{ActionInfo, RunRecord, query_actions, query_runs};
+
+fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn run_lines(run: &RunRecord, lines: &mut Vec<String>) {
+    // 未结束的运行可能还在执行中，或者被中断
+    let ended = run.ended.map_or("unfinished".to_string(), format_time);
+    let undone = run
+        .undone
+        .map(|t| format!(" undone at {}", format_time(t)))
+        .unwrap_or_default();
+    lines.push(format!(
+        "run {} {} -> {} ({}){}",
+        run.id,
+        format_time(run.started),
+        ended,
+        run.version.as_deref().unwrap_or("unknown"),
+        undone
+    ));
+    lines.push(format!(
+        "  {} -> {}",
+        run.input.as_deref().unwrap_or("-"),
+        run.output.as_deref().unwrap_or("-")
+    ));
+    if let Some(counts) = &run.counts {
+        lines.push(format!("  {}", counts));
+    }
+}
+
+/// the result of `place::history`, all the runs, or the given run with its actions.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryReport {
+    pub runs: Vec<RunRecord>,
+    /// the changes made by the given run, empty if listing all the runs
+    pub actions: Vec<ActionInfo>,
+}
+
+impl HistoryReport {
+    /// the lines printed by the `history` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for run in self.runs.iter() {
+            run_lines(run, &mut lines);
+        }
+        for action in self.actions.iter() {
+            lines.push(format!(
+                "  {:<18} {} -> {} {}",
+                action.action,
+                action.source.as_deref().unwrap_or("-"),
+                action.destination.as_deref().unwrap_or("-"),
+                action.hash
+            ));
+            if let Some(trash) = &action.trash {
+                lines.push(format!("  {:<18} moved to {}", "", trash));
+            }
+        }
+        lines
+    }
+}
+
+// 不指定 run 时列出所有的运行记录，否则列出该次运行的所有修改
+pub fn do_history(ctx: &Context, run: Option<i64>) -> Result<HistoryReport> {
+    let conn = ctx.conn()?.lock().unwrap();
+    let runs = query_runs(&conn)?;
+    let Some(id) = run else {
+        return Ok(HistoryReport {
+            runs,
+            actions: vec![],
+        });
+    };
+
+    let run = runs
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or(anyhow::anyhow!("run {} not found", id))?;
+    Ok(HistoryReport {
+        runs: vec![run],
+        actions: query_actions(&conn, id)?,
+    })
+}
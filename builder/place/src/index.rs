@@ -0,0 +1,274 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde_json::json;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, finish_run, insert_action, insert_finfo, insert_run, query_finfo,
+    update_finfo,
+};
+use super::input::{Inputs, WalkOptions};
+use super::process::parse_metadata;
+use super::target::{OUTPUT_GEN, Target, UNKNOWN_DATE_DIR, is_internal};
+
+// 归档文件相对归档目录的路径即为 parts
+fn relative_parts(file: &Path, output: &Path) -> Option<Vec<String>> {
+    let parts: Vec<String> = file
+        .strip_prefix(output)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    (!parts.is_empty()).then_some(parts)
+}
+
+// 计算 hash，earliest 使用文件修改时间或者重新解析元数据
+async fn index_target(ctx: &Context, file: PathBuf, parse: bool) -> Result<Target> {
+    let algorithm = ctx.hash_algorithm().to_string();
+    let mut target = tokio::task::spawn_blocking(move || Target::new(file, &algorithm)).await??;
+    if parse {
+        parse_metadata(ctx, &mut target).await?;
+    } else {
+        let modified: DateTime<Local> = std::fs::metadata(&target.path)?.modified()?.into();
+        target.set_earliest(Some(modified.timestamp() as u64))?;
+    }
+    Ok(target)
+}
+
+/// the result of `place::index`.
+#[derive(Debug, Clone, Default)]
+pub struct IndexReport {
+    pub run: i64,
+    pub inserted: usize,
+    /// the records pointed to the file, the recorded one does not exist
+    pub updated: usize,
+    pub unchanged: usize,
+    /// the relative paths of the files with the same content as the recorded one, and the recorded path
+    pub duplicates: Vec<(PathBuf, String)>,
+}
+
+impl IndexReport {
+    /// the lines printed by the `index` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .duplicates
+            .iter()
+            .map(|(file, same)| format!("duplicate {} (same as {})", file.display(), same))
+            .collect();
+        lines.push(format!(
+            "{} inserted, {} updated, {} unchanged, {} duplicate, run {}",
+            self.inserted,
+            self.updated,
+            self.unchanged,
+            self.duplicates.len(),
+            self.run
+        ));
+        lines
+    }
+}
+
+// 遍历已存在的归档目录，将文件按相对路径写入数据库，不移动任何文件
+pub async fn do_index(ctx: &Context, output: PathBuf, parse: bool) -> Result<IndexReport> {
+    let inputs = Inputs::Roots {
+        roots: vec![output.clone()],
+        walk: WalkOptions::default(),
+    };
+    let mut files: Vec<PathBuf> = inputs
+        .files(None)
+        .filter(|f| !is_internal(f, &output))
+        .collect();
+    // 内容相同的文件保留路径排序靠前的
+    files.sort();
+    info!(total = files.len(), output=?output, parse, "start index the archive");
+
+    let semaphore = Arc::new(Semaphore::new(ctx.batch()));
+    let mut handles = Vec::new();
+    for file in files {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let ctx = ctx.clone();
+        handles.push(tokio::spawn(async move {
+            let _permit = permit;
+            (file.clone(), index_target(&ctx, file, parse).await)
+        }));
+    }
+
+    let mut targets = Vec::new();
+    for handle in handles {
+        targets.push(handle.await?);
+    }
+
+    let started = Local::now().timestamp();
+    let conn = ctx.conn()?.lock().unwrap();
+    let run = insert_run(&conn, started)?;
+    let mut indexed = IndexReport {
+        run: run.id,
+        ..Default::default()
+    };
+    for (file, target) in targets {
+        let target = match target {
+            Ok(t) => t,
+            Err(e) => {
+                warn!(file=?file, error=%e, "⚠️ index file failed, skip it");
+                continue;
+            }
+        };
+        let Some(parts) = relative_parts(&file, &output) else {
+            continue;
+        };
+        // 文件在 hash 之后被删除时跳过，不中断整个索引
+        let size = match std::fs::metadata(&file) {
+            Ok(m) => m.len() as i64,
+            Err(e) => {
+                warn!(file=?file, error=%e, "⚠️ index file failed, skip it");
+                continue;
+            }
+        };
+        let finfo = FileInfo {
+            parts: Cow::Borrowed(&parts),
+            hash: Cow::Borrowed(&target.hash),
+            algorithm: Cow::Borrowed(ctx.hash_algorithm()),
+            earliest: target.get_earliest()?.timestamp(),
+            size: Some(size),
+            source: None,
+            first_seen: Some(started),
+            last_seen: Some(started),
+            media: target.media.clone(),
+            // 重新解析时没有元数据时间，或者在 `unknown-date/` 中的文件待确认
+            undated: (parse && target.is_undated()) || parts[0] == UNKNOWN_DATE_DIR,
+        };
+        let action = ActionInfo {
+            run_id: run.id,
+            hash: target.hash.clone(),
+            source: Some(file.to_string_lossy().to_string()),
+            destination: Some(file.to_string_lossy().to_string()),
+            timestamp: Local::now().timestamp(),
+            ..Default::default()
+        };
+        match query_finfo(&conn, &target.hash)? {
+            None => {
+                insert_finfo(&conn, &finfo)?;
+                insert_action(
+                    &conn,
+                    &ActionInfo {
+                        action: "insert".to_string(),
+                        ..action
+                    },
+                )?;
+                indexed.inserted += 1;
+            }
+            Some(history) if history.parts.as_ref() == parts.as_slice() => {
+                indexed.unchanged += 1;
+            }
+            // 记录的文件还在，说明归档中有重复的文件
+            Some(history) if OUTPUT_GEN(&output, &history.parts).is_file() => {
+                indexed.duplicates.push((
+                    file.strip_prefix(&output).unwrap_or(&file).to_path_buf(),
+                    history.parts.join("/"),
+                ));
+            }
+            // 记录的文件已不存在，指向当前文件
+            Some(history) => {
+                update_finfo(&conn, &finfo)?;
+                insert_action(
+                    &conn,
+                    &ActionInfo {
+                        action: "update".to_string(),
+                        previous: Some(serde_json::to_string(&history)?),
+                        ..action
+                    },
+                )?;
+                indexed.updated += 1;
+            }
+        }
+    }
+
+    finish_run(
+        &conn,
+        run,
+        &output,
+        json!({ "index": output }),
+        Some(json!({ "parse": parse })),
+        json!({
+            "inserted": indexed.inserted,
+            "updated": indexed.updated,
+            "unchanged": indexed.unchanged,
+            "duplicate": indexed.duplicates.len(),
+        }),
+    )?;
+    info!(run = run.id, indexed = ?indexed, "index the archive done");
+    Ok(indexed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_parts() {
+        let output = Path::new("/to");
+        assert_eq!(
+            relative_parts(Path::new("/to/2002/11/a.jpg"), output),
+            Some(vec![
+                "2002".to_string(),
+                "11".to_string(),
+                "a.jpg".to_string()
+            ])
+        );
+        assert_eq!(relative_parts(Path::new("/from/a.jpg"), output), None);
+        assert_eq!(relative_parts(output, output), None);
+    }
+
+    #[tokio::test]
+    async fn test_do_index() {
+        let output = std::env::temp_dir().join("mmfplace_test_do_index");
+        let _ = std::fs::remove_dir_all(&output);
+        for (path, content) in [
+            ("2002/11/a.jpg", "a"),
+            ("2002/11/b.jpg", "b"),
+            ("2003/01/a_copy.jpg", "a"),
+            ("unknown-date/2004/02/c.jpg", "c"),
+        ] {
+            let file = output.join(path);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let counts = |run: usize| {
+            let runs = crate::db::query_runs(&ctx.conn().unwrap().lock().unwrap()).unwrap();
+            serde_json::from_str::<serde_json::Value>(runs[run].counts.as_deref().unwrap()).unwrap()
+        };
+
+        // the copy sorted after the first one is a duplicate
+        do_index(&ctx, output.clone(), false).await.unwrap();
+        assert_eq!(
+            counts(0),
+            json!({ "inserted": 3, "updated": 0, "unchanged": 0, "duplicate": 1 })
+        );
+        let a = utils::crypto::get_file_hash(ctx.hash_algorithm(), &output.join("2002/11/a.jpg"))
+            .unwrap();
+        {
+            let conn = ctx.conn().unwrap().lock().unwrap();
+            let finfo = query_finfo(&conn, &a).unwrap().unwrap();
+            assert_eq!(finfo.parts.join("/"), "2002/11/a.jpg");
+            assert_eq!(crate::db::query_finfo_undated(&conn).unwrap().len(), 1);
+        }
+
+        // the recorded file is removed, the record points to the copy
+        std::fs::remove_file(output.join("2002/11/a.jpg")).unwrap();
+        do_index(&ctx, output.clone(), false).await.unwrap();
+        assert_eq!(
+            counts(1),
+            json!({ "inserted": 0, "updated": 1, "unchanged": 2, "duplicate": 0 })
+        );
+        let finfo = query_finfo(&ctx.conn().unwrap().lock().unwrap(), &a)
+            .unwrap()
+            .unwrap();
+        assert_eq!(finfo.parts.join("/"), "2003/01/a_copy.jpg");
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
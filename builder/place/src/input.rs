@@ -0,0 +1,291 @@
+use anyhow::Result;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+/// the errors occurred when walking the input roots, e.g. permission denied, broken links.
+pub(crate) type WalkErrors = Arc<Mutex<Vec<String>>>;
+
+/// how the input roots are walked.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// follow symbolic links, the link loops are detected and reported as walk errors
+    pub follow_links: bool,
+    /// do not cross the file system boundary of each root
+    pub same_file_system: bool,
+    /// the maximum depth to descend, the root itself is depth 0
+    pub max_depth: Option<usize>,
+}
+
+/// the files to be placed, walked from the input roots or listed explicitly.
+#[derive(Debug, Clone)]
+pub enum Inputs {
+    /// directories (or files) to walk
+    Roots {
+        roots: Vec<PathBuf>,
+        walk: WalkOptions,
+    },
+    /// explicit file list, e.g. from `--files-from`
+    Files(Vec<PathBuf>),
+}
+
+impl Inputs {
+    // 遍历所有需要处理的文件，只处理普通文件，跳过设备、管道等特殊文件
+    // 遍历错误记录到 errors 中，不传则忽略(如仅统计数量)
+    pub(crate) fn files(
+        &self,
+        errors: Option<WalkErrors>,
+    ) -> Box<dyn Iterator<Item = PathBuf> + Send + '_> {
+        match self {
+            Inputs::Roots { roots, walk } => Box::new(roots.iter().flat_map(move |input| {
+                let errors = errors.clone();
+                let mut walker = WalkDir::new(input)
+                    .follow_links(walk.follow_links)
+                    .same_file_system(walk.same_file_system);
+                if let Some(depth) = walk.max_depth {
+                    walker = walker.max_depth(depth);
+                }
+                walker
+                    .into_iter()
+                    .filter_map(move |entry| match entry {
+                        Ok(e) => Some(e),
+                        Err(e) => {
+                            if let Some(errors) = &errors {
+                                warn!(error=%e, "⚠️ walk error");
+                                errors.lock().unwrap().push(e.to_string());
+                            }
+                            None
+                        }
+                    })
+                    .filter(|e| {
+                        let ftype = e.file_type();
+                        if !ftype.is_file() && !ftype.is_dir() {
+                            debug!(file=?e.path(), "💡 skip the symlink or special file");
+                        }
+                        ftype.is_file()
+                    })
+                    .map(|e| e.into_path())
+            })),
+            Inputs::Files(files) => Box::new(files.iter().cloned()),
+        }
+    }
+
+    // 规范化输入: 目录去重，列表中不存在的文件进行上报并跳过
+    pub(crate) fn normalize(self) -> Result<Self> {
+        match self {
+            Inputs::Roots { roots, walk } => Ok(Inputs::Roots {
+                roots: normalize_roots(&roots)?,
+                walk,
+            }),
+            Inputs::Files(files) => {
+                let (mut exists, mut missing) = (Vec::new(), Vec::new());
+                for file in files {
+                    match file.canonicalize() {
+                        Ok(f) if f.is_file() => exists.push(f),
+                        _ => {
+                            warn!(file=?file, "⚠️ the listed file not found or not a regular file");
+                            missing.push(file);
+                        }
+                    }
+                }
+                let mut seen = std::collections::HashSet::new();
+                exists.retain(|f| seen.insert(f.clone()));
+                if !missing.is_empty() {
+                    warn!(
+                        missing = missing.len(),
+                        found = exists.len(),
+                        "⚠️ some listed files are missing and will be skipped"
+                    );
+                }
+                Ok(Inputs::Files(exists))
+            }
+        }
+    }
+}
+
+/// read the input roots from a list file, one path per line, empty lines and `#` comments are skipped.
+/// relative paths are resolved against the directory of the list file.
+pub fn read_input_list(list: &Path) -> Result<Vec<PathBuf>> {
+    let content = std::fs::read_to_string(list)
+        .map_err(|e| anyhow::anyhow!("read input list {:?} error: {}", list, e))?;
+    let base = list.parent().unwrap_or(Path::new("."));
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| base.join(l))
+        .collect())
+}
+
+/// read an explicit file list from a file or stdin (`-`), the entries are delimited by NUL if any,
+/// otherwise by newline. relative paths are resolved against `base`.
+pub fn read_files_from(from: &Path, base: &Path) -> Result<Vec<PathBuf>> {
+    let mut content = Vec::new();
+    if from == Path::new("-") {
+        std::io::stdin().read_to_end(&mut content)?;
+    } else {
+        std::fs::File::open(from)
+            .and_then(|mut f| f.read_to_end(&mut content))
+            .map_err(|e| anyhow::anyhow!("read files from {:?} error: {}", from, e))?;
+    }
+    let delimiter = if content.contains(&0) { b'\0' } else { b'\n' };
+    let files: Vec<PathBuf> = content
+        .split(|b| *b == delimiter)
+        .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+        .filter(|l| !l.is_empty())
+        .map(|l| base.join(bytes_to_path(l)))
+        .collect();
+    info!(from=?from, count=files.len(), "read file list");
+    Ok(files)
+}
+
+// unix 下路径可以是任意字节，如 `find -print0` 输出的非 UTF-8 文件名，不能有损转换
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).to_string())
+}
+
+// 多个输入目录时，去掉重复的以及被其他输入包含的目录，避免同一个文件被遍历多次
+pub(crate) fn normalize_roots(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut roots = Vec::new();
+    for input in inputs {
+        let root = input
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("input {:?} error: {}", input, e))?;
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    let nested: Vec<PathBuf> = roots
+        .iter()
+        .filter(|r| roots.iter().any(|o| o != *r && r.starts_with(o)))
+        .cloned()
+        .collect();
+    for n in nested.iter() {
+        warn!(input=?n, "⚠️ the input is inside another input, skip it");
+    }
+    roots.retain(|r| !nested.contains(r));
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_options() {
+        let root = std::env::temp_dir().join("mmfplace_test_walk_options");
+        if root.exists() {
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.jpg"), "a").unwrap();
+        std::fs::write(root.join("sub").join("b.jpg"), "b").unwrap();
+        std::os::unix::fs::symlink(root.join("missing.jpg"), root.join("broken.jpg")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("sub").join("loop")).unwrap();
+
+        let walk = |walk: WalkOptions| {
+            let inputs = Inputs::Roots {
+                roots: vec![root.clone()],
+                walk,
+            };
+            let errors = WalkErrors::default();
+            let count = inputs.files(Some(errors.clone())).count();
+            let errors = errors.lock().unwrap().len();
+            (count, errors)
+        };
+        // the symlinks are skipped by default
+        assert_eq!(walk(WalkOptions::default()), (2, 0));
+        // the broken link and the loop are reported
+        let follow = WalkOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        assert_eq!(walk(follow), (2, 2));
+        let depth = WalkOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(walk(depth), (1, 0));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_roots() {
+        let root = std::env::temp_dir().join("mmfplace_test_normalize_roots");
+        std::fs::create_dir_all(root.join("a").join("sub")).unwrap();
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        // the duplicated and nested roots are removed, the order is kept
+        let roots = normalize_roots(&[
+            root.join("b"),
+            root.join("a").join("sub"),
+            root.join("a"),
+            root.join("b").join("..").join("b"),
+        ])
+        .unwrap();
+        assert_eq!(roots, vec![root.join("b"), root.join("a")]);
+        assert!(normalize_roots(&[root.join("missing")]).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_read_input_list() {
+        let dir = std::env::temp_dir().join("mmfplace_test_input_list");
+        std::fs::create_dir_all(&dir).unwrap();
+        let list = dir.join("inputs.txt");
+        std::fs::write(&list, "# photos\n  /abs/photos  \n\nphones/a\n").unwrap();
+        let roots = read_input_list(&list).unwrap();
+        assert_eq!(
+            roots,
+            vec![PathBuf::from("/abs/photos"), dir.join("phones/a")]
+        );
+        assert!(read_input_list(&dir.join("missing.txt")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_files_from() {
+        let base = PathBuf::from("/base");
+        let list = std::env::temp_dir().join("mmfplace_test_files_from.txt");
+        std::fs::write(&list, "a.jpg\n\n/abs/b.png\r\n").unwrap();
+        let files = read_files_from(&list, &base).unwrap();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("/base/a.jpg"), PathBuf::from("/abs/b.png")]
+        );
+
+        std::fs::write(&list, "a b.jpg\0c\nd.jpg\0").unwrap();
+        let files = read_files_from(&list, &base).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from("/base/a b.jpg"),
+                PathBuf::from("/base/c\nd.jpg")
+            ]
+        );
+
+        // the file names which are not valid UTF-8 are kept as is
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            std::fs::write(&list, b"caf\xe9.jpg\0").unwrap();
+            let files = read_files_from(&list, &base).unwrap();
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].file_name().unwrap().as_bytes(), b"caf\xe9.jpg");
+        }
+        std::fs::remove_file(list).unwrap();
+    }
+}
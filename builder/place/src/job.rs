@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::analyze::FileReport;
+use super::input::{Inputs, WalkOptions};
+use super::process::Placed;
+use super::progress::{Progress, ProgressFn};
+use super::summary::{FailedFile, RunSummary};
+
+/// the options of a place job, built by chaining the setters.
+///
+/// ```no_run
+/// let options = place::PlaceOptions::new("/archive").input("/photos").rename_with_ymd(true);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PlaceOptions {
+    pub(crate) inputs: Inputs,
+    pub(crate) output: PathBuf,
+    pub(crate) test: bool,
+    pub(crate) rename_with_ymd: bool,
+    pub(crate) rehash: bool,
+    pub(crate) unknown_date: bool,
+    pub(crate) progress: Option<ProgressFn>,
+}
+
+impl PlaceOptions {
+    pub fn new(output: impl Into<PathBuf>) -> Self {
+        PlaceOptions {
+            inputs: Inputs::Roots {
+                roots: vec![],
+                walk: WalkOptions::default(),
+            },
+            output: output.into(),
+            test: false,
+            rename_with_ymd: false,
+            rehash: false,
+            unknown_date: false,
+            progress: None,
+        }
+    }
+
+    /// add an input directory or file to walk, replaces the explicit file list if set.
+    pub fn input(mut self, root: impl Into<PathBuf>) -> Self {
+        match &mut self.inputs {
+            Inputs::Roots { roots, .. } => roots.push(root.into()),
+            Inputs::Files(_) => {
+                self.inputs = Inputs::Roots {
+                    roots: vec![root.into()],
+                    walk: WalkOptions::default(),
+                }
+            }
+        }
+        self
+    }
+
+    /// replace all the inputs, e.g. an explicit file list.
+    pub fn inputs(mut self, inputs: Inputs) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    /// how the input directories are walked, ignored for the explicit file list.
+    pub fn walk(mut self, options: WalkOptions) -> Self {
+        if let Inputs::Roots { walk, .. } = &mut self.inputs {
+            *walk = options;
+        }
+        self
+    }
+
+    /// only parse the files and generate the output paths, nothing is copied or recorded.
+    pub fn test(mut self, test: bool) -> Self {
+        self.test = test;
+        self
+    }
+
+    /// name the placed files by the earliest date, `%Y-%m-%d`.
+    pub fn rename_with_ymd(mut self, rename: bool) -> Self {
+        self.rename_with_ymd = rename;
+        self
+    }
+
+    /// rehash all the files instead of using the source index.
+    pub fn rehash(mut self, rehash: bool) -> Self {
+        self.rehash = rehash;
+        self
+    }
+
+    /// place the files without any datetime in metadata into `unknown-date/YYYY/MM/` by the file times,
+    /// they are listed by `review list` in either case.
+    pub fn unknown_date(mut self, unknown_date: bool) -> Self {
+        self.unknown_date = unknown_date;
+        self
+    }
+
+    /// call the function with the progress at most every 200ms, and when the phase is changed to done.
+    pub fn on_progress(mut self, callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(ProgressFn(Arc::new(callback)));
+        self
+    }
+
+    /// redraw the progress line at the bottom of the terminal, only logs if the stderr is not a terminal.
+    pub fn terminal_progress(mut self) -> Self {
+        self.progress = crate::progress::terminal();
+        self
+    }
+
+    pub fn get_inputs(&self) -> &Inputs {
+        &self.inputs
+    }
+
+    pub fn get_output(&self) -> &Path {
+        &self.output
+    }
+}
+
+/// the events of a place job, see `place::process_stream`.
+#[derive(Debug, Clone)]
+pub enum PlaceEvent {
+    /// the run is started, the id is 0 in test mode
+    Started { run: i64, total: usize },
+    /// a file is handled, the report has the output path, every file is `Planned` in test mode
+    Placed {
+        file: Box<FileReport>,
+        placed: Placed,
+    },
+    /// a file is not placed because of an error, the other files are still handled
+    Failed(FailedFile),
+    /// all the files are handled
+    Finished(Box<RunSummary>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let tests = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("../../tests")
+            .canonicalize()
+            .unwrap();
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let options = PlaceOptions::new(&tests)
+            .inputs(Inputs::Files(vec![tests.join("2002/11/simple.png")]))
+            .test(true);
+        let events: Vec<PlaceEvent> = crate::process_stream(&ctx, options)
+            .map(|e| e.unwrap())
+            .collect()
+            .await;
+        println!("events: {:#?}", events);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[0],
+            PlaceEvent::Started { run: 0, total: 1 }
+        ));
+        let PlaceEvent::Placed { file, placed } = &events[1] else {
+            panic!("expected placed event");
+        };
+        assert_eq!(*placed, Placed::Planned);
+        assert_eq!(file.output, Some(tests.join("2002/11/simple_01.jpg")));
+        assert!(matches!(&events[2], PlaceEvent::Finished(s) if s.counts.total == 1 && s.test));
+    }
+
+    // the contexts have their own configs and databases in one process
+    #[tokio::test]
+    async fn test_process_with_contexts() {
+        let tests = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("../../tests")
+            .canonicalize()
+            .unwrap();
+        let input = tests.join("2002/11/simple.jpg");
+        let (mut md5, mut sha256) = (config::CONFIG.clone(), config::CONFIG.clone());
+        md5.hash = Some("md5".to_string());
+        sha256.hash = Some("sha256".to_string());
+        let mut nojava = sha256.clone();
+        nojava.java = Some("/nonexistent/mmfplace/java".to_string());
+        let contexts = [md5, sha256, nojava].map(|c| Context::new(c, ":memory:"));
+        let outputs = ["md5", "sha256", "nojava"]
+            .map(|n| std::env::temp_dir().join(format!("mmfplace_test_contexts_{}", n)));
+        for output in outputs.iter().filter(|o| o.exists()) {
+            std::fs::remove_dir_all(output).unwrap();
+        }
+
+        let run = |i: usize| {
+            let options = PlaceOptions::new(&outputs[i]).inputs(Inputs::Files(vec![input.clone()]));
+            crate::process_with(&contexts[i], options)
+        };
+        let (a, b, c) = tokio::join!(run(0), run(1), run(2));
+        let (a, b, c) = (a.unwrap(), b.unwrap(), c.unwrap());
+        assert_eq!((a.counts.created, a.counts.failed), (1, 0));
+        assert_eq!((b.counts.created, b.counts.failed), (1, 0));
+        // the java runtime of the context is used
+        assert_eq!((c.counts.created, c.counts.failed), (0, 1));
+
+        for (ctx, hash) in contexts.iter().zip([
+            Some("a18932e314dbb4c81c6fd0e282d81d16"),
+            Some("0291b9bf797a3f59684c7e5817eb5b948796bc4271e004bc76515dabecadcee7"),
+            None,
+        ]) {
+            let finfos = crate::db::query_finfo_all(&ctx.conn().unwrap().lock().unwrap()).unwrap();
+            let hashes = finfos.iter().map(|f| f.hash.as_ref()).collect::<Vec<_>>();
+            assert_eq!(hashes, hash.into_iter().collect::<Vec<_>>());
+        }
+        for output in outputs.iter().filter(|o| o.exists()) {
+            std::fs::remove_dir_all(output).unwrap();
+        }
+    }
+}
@@ -1,24 +1,198 @@
 use anyhow::Result;
-use std::path::PathBuf;
+use futures::{Stream, StreamExt};
+use std::path::{Path, PathBuf};
 
+mod analyze;
+mod confcheck;
+mod context;
 mod db;
+mod dupes;
+mod dupf;
+mod history;
+mod index;
+mod input;
+mod job;
+mod media;
+mod migrate;
+mod overrides;
+mod phash;
 mod process;
+mod progress;
+mod relayout;
+mod report;
+mod review;
+mod summary;
 mod target;
+mod undo;
+mod verify;
 
-pub async fn process(
-    input: &PathBuf,
-    output: &Option<PathBuf>,
-    test: bool,
+pub use analyze::{FileReport, ParsedTime, analyze, hash_file, parse_datetime, plan};
+pub use confcheck::ConfigReport;
+pub use config::Config;
+pub use context::Context;
+pub use db::{ActionInfo, RunRecord, SourceInfo};
+pub use dupes::{DupeImage, DupesReport, KeepPolicy};
+pub use dupf::{DupfAction, DupfFormat, DupfGroup, DupfReport};
+pub use history::HistoryReport;
+pub use index::IndexReport;
+pub use input::{Inputs, WalkOptions, read_files_from, read_input_list};
+pub use job::{PlaceEvent, PlaceOptions};
+pub use media::MediaInfo;
+pub use overrides::SetDateReport;
+pub use process::{Placed, RunCounts};
+pub use progress::{Phase, Progress, format_progress};
+pub use relayout::RelayoutReport;
+pub use report::{OriginReport, Unarchived, UnarchivedReport};
+pub use review::{ReviewReport, UndatedFile};
+pub use summary::{FailedFile, RunSummary};
+pub use target::{ConflictPolicy, Provenance};
+pub use undo::UndoReport;
+pub use verify::{RepairStatus, VerifyIssue, VerifyReport};
+
+// 创建输出目录并规范化输入和输出路径
+fn prepare(mut options: PlaceOptions) -> Result<PlaceOptions> {
+    if !options.output.is_dir() {
+        std::fs::create_dir_all(&options.output)?;
+    }
+    options.output = options.output.canonicalize()?;
+    options.inputs = options.inputs.normalize()?;
+    Ok(options)
+}
+
+/// place the files with the given context, returns the summary of the run even if some files failed,
+/// see `RunSummary::check`.
+pub async fn process_with(ctx: &Context, options: PlaceOptions) -> Result<RunSummary> {
+    process::do_process(ctx, prepare(options)?, None).await
+}
+
+/// place the files in a background task, and stream the events of each file,
+/// the error of the job is the last item. Must be called in a tokio runtime.
+pub fn process_stream(
+    ctx: &Context,
+    options: PlaceOptions,
+) -> impl Stream<Item = Result<PlaceEvent>> + Send + 'static {
+    let (tx, rx) = futures::channel::mpsc::unbounded();
+    let ctx = ctx.clone();
+    let handle =
+        tokio::spawn(async move { process::do_process(&ctx, prepare(options)?, Some(tx)).await });
+    // 所有事件发送之后，发送端关闭，再返回任务的错误
+    let result = futures::stream::once(async move {
+        match handle.await {
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(e) => Some(Err(e.into())),
+        }
+    })
+    .filter_map(futures::future::ready);
+    rx.map(Ok).chain(result)
+}
+
+/// rehash the archived files with the configured hash algorithm, see config `hash`.
+pub async fn migrate_hash(ctx: &Context, output: &Path, prune: bool) -> Result<()> {
+    migrate::do_migrate_hash(ctx, output.to_path_buf(), prune)
+}
+
+/// report the clusters of near-duplicate images in the archive by perceptual hash.
+pub async fn dupes(
+    ctx: &Context,
+    output: &Path,
+    threshold: f64,
+    keep: KeepPolicy,
+) -> Result<DupesReport> {
+    dupes::do_dupes(ctx, output.to_path_buf(), threshold, keep).await
+}
+
+/// find the files with the same content in the input directories, see `DupfReport::apply` for the actions.
+pub async fn dupf(ctx: &Context, roots: Vec<PathBuf>) -> Result<DupfReport> {
+    if roots.is_empty() {
+        return Err(anyhow::anyhow!("no input specified"));
+    }
+    let inputs = Inputs::Roots {
+        roots,
+        walk: WalkOptions::default(),
+    }
+    .normalize()?;
+    dupf::do_dupf(ctx, inputs).await
+}
+
+/// list the files in each input root which are not archived yet, the archived files are checked if output is given,
+/// see `UnarchivedReport::check` for the roots not fully walked.
+pub async fn report(
+    ctx: &Context,
+    roots: Vec<PathBuf>,
+    output: Option<&Path>,
+) -> Result<UnarchivedReport> {
+    let roots = input::normalize_roots(&roots)?;
+    report::do_report(ctx, roots, output.map(Path::to_path_buf)).await
+}
+
+/// list all the source paths seen for the file or hash.
+pub fn origin(ctx: &Context, target: &str) -> Result<OriginReport> {
+    report::do_origin(ctx, target)
+}
+
+/// list the runs, or the changes made by the given run.
+pub fn history(ctx: &Context, run: Option<i64>) -> Result<HistoryReport> {
+    history::do_history(ctx, run)
+}
+
+/// undo the changes made by the given run, refuse if the archived files were modified since.
+pub fn undo(ctx: &Context, run: i64) -> Result<UndoReport> {
+    undo::do_undo(ctx, run)
+}
+
+/// verify the archived files with the database, and optionally repair them from the sources.
+pub async fn verify(ctx: &Context, output: &Path, repair: bool) -> Result<VerifyReport> {
+    verify::do_verify(ctx, output.to_path_buf(), repair).await
+}
+
+/// index an existing archive into the database by the relative paths, without moving any file.
+pub async fn index(ctx: &Context, output: &Path, parse: bool) -> Result<IndexReport> {
+    index::do_index(ctx, output.to_path_buf(), parse).await
+}
+
+/// move the archived files to the paths generated by the current layout, only plan the moves if dry run.
+pub fn relayout(
+    ctx: &Context,
+    output: &Path,
     rename_with_ymd: bool,
-) -> Result<()> {
-    let output = if let Some(o) = output {
-        o
-    } else {
-        &input.with_extension("mmfplace")
+    dry_run: bool,
+) -> Result<RelayoutReport> {
+    relayout::do_relayout(ctx, output.to_path_buf(), rename_with_ymd, dry_run)
+}
+
+/// list the archived files without any datetime in metadata.
+pub fn review_list(ctx: &Context) -> Result<ReviewReport> {
+    review::do_review_list(ctx)
+}
+
+/// assign the date to a file or hash, or to each `<path|hash>,<date>` line of the csv file.
+/// The archived files are moved into the new directory, the others use the date when placed,
+/// see `SetDateReport::check` for the failed entries.
+pub fn set_date(
+    ctx: &Context,
+    output: &Path,
+    target: Option<&str>,
+    date: Option<&str>,
+    csv: Option<&Path>,
+) -> Result<SetDateReport> {
+    let (entries, input) = match (csv, target, date) {
+        (Some(csv), _, _) => (overrides::read_csv(csv)?, csv.to_string_lossy().to_string()),
+        (None, Some(target), Some(date)) => (
+            vec![(target.to_string(), date.to_string())],
+            target.to_string(),
+        ),
+        _ => return Err(anyhow::anyhow!("the file/hash and date must be specified")),
     };
-    if !output.is_dir() {
-        std::fs::create_dir_all(&output)?;
-    }
-    let (input, output) = (input.canonicalize()?, output.canonicalize()?);
-    process::do_process(input, output, test, rename_with_ymd).await
+    overrides::do_set_date(ctx, output.to_path_buf(), entries, &input)
+}
+
+/// check the config files with `config` (`--config`), and the rules in them.
+pub fn config_check(config: Option<&Path>) -> Result<ConfigReport> {
+    confcheck::do_config_check(config)
+}
+
+/// the effective config merged from the config files with `config` (`--config`).
+pub fn config_show(config: Option<&Path>) -> Result<Config> {
+    confcheck::do_config_show(config)
 }
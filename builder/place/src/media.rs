@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+/// the media info parsed from the metadata extractor output.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    /// the detected mime type, e.g. `image/jpeg`
+    pub mime: Option<String>,
+    /// the camera make and model
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// the dimensions in pixels
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    /// the duration in seconds of video/audio
+    pub duration: Option<f64>,
+    /// the GPS coordinates in decimal degrees, negative for south/west
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+// 同一个字段可能出现在多个目录中，rank 越小越优先
+#[derive(Debug, Default)]
+struct Ranked<T>(Option<(u8, T)>);
+
+impl<T> Ranked<T> {
+    fn set(&mut self, rank: u8, value: Option<T>) {
+        if let Some(value) = value
+            && self.0.as_ref().is_none_or(|(r, _)| rank < *r)
+        {
+            self.0 = Some((rank, value));
+        }
+    }
+
+    fn get(self) -> Option<T> {
+        self.0.map(|(_, v)| v)
+    }
+}
+
+// `[Directory] Tag = Value`
+fn split_line(line: &str) -> Option<(&str, &str, &str)> {
+    let line = line.strip_prefix('[')?;
+    let (directory, rest) = line.split_once("] ")?;
+    let (tag, value) = rest.split_once(" = ")?;
+    Some((directory, tag, value.trim()))
+}
+
+// `600 pixels` -> 600
+fn parse_pixels(value: &str) -> Option<i64> {
+    value
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+        .filter(|v| *v > 0)
+}
+
+// `00:01:02.5`, `62.5 seconds`, `62.5`
+fn parse_duration(value: &str) -> Option<f64> {
+    let value = value.trim_end_matches(" seconds").trim_end_matches(" s");
+    let seconds = value
+        .split(':')
+        .try_fold(0f64, |acc, v| {
+            v.trim().parse::<f64>().map(|v| acc * 60.0 + v)
+        })
+        .ok()?;
+    (seconds > 0.0).then_some(seconds)
+}
+
+// `54- 59' 22.8"`，非 ascii 的 `°` 已经被替换为 `-`
+fn parse_degrees(value: &str, reference: Option<&str>) -> Option<f64> {
+    let mut parts = value
+        .split(|c: char| c == '\'' || c == '"' || c.is_whitespace())
+        .filter(|s| !s.is_empty());
+    let degrees = parts.next()?;
+    let negative = degrees.starts_with('-');
+    let degrees: f64 = degrees.trim_matches('-').parse().ok()?;
+    let minutes: f64 = parts.next().map_or(Some(0.0), |m| m.parse().ok())?;
+    let seconds: f64 = parts.next().map_or(Some(0.0), |s| s.parse().ok())?;
+    let decimal = degrees + minutes / 60.0 + seconds / 3600.0;
+    let negative = negative || matches!(reference, Some("S") | Some("W"));
+    Some(if negative { -decimal } else { decimal })
+}
+
+impl MediaInfo {
+    pub fn from_texts<'a>(texts: impl IntoIterator<Item = &'a String>) -> Self {
+        let (mut mime, mut make, mut model) =
+            (Ranked::default(), Ranked::default(), Ranked::default());
+        let (mut width, mut height, mut duration) =
+            (Ranked::default(), Ranked::default(), Ranked::default());
+        let (mut lat, mut lat_ref, mut lon, mut lon_ref) = (None, None, None, None);
+
+        for (directory, tag, value) in texts.into_iter().filter_map(|t| split_line(t)) {
+            // 缩略图的尺寸等信息不是文件本身的
+            if directory.contains("Thumbnail") {
+                continue;
+            }
+            let text = || Some(value.to_string()).filter(|v| !v.is_empty());
+            match (directory, tag) {
+                ("File Type", "Detected MIME Type") => mime.set(0, text()),
+                (d, "Make") => make.set(if d == "Exif IFD0" { 0 } else { 1 }, text()),
+                (d, "Model") => model.set(if d == "Exif IFD0" { 0 } else { 1 }, text()),
+                // 优先使用文件本身的尺寸，exif 中记录的可能是原图的尺寸
+                (_, "Image Width") => width.set(0, parse_pixels(value)),
+                (_, "Image Height") => height.set(0, parse_pixels(value)),
+                (_, "Exif Image Width") => width.set(1, parse_pixels(value)),
+                (_, "Exif Image Height") => height.set(1, parse_pixels(value)),
+                (_, "Width") => width.set(2, parse_pixels(value)),
+                (_, "Height") => height.set(2, parse_pixels(value)),
+                (_, "Duration in Seconds") => duration.set(0, parse_duration(value)),
+                (_, "Duration") => duration.set(1, parse_duration(value)),
+                ("GPS", "GPS Latitude") => lat = Some(value),
+                ("GPS", "GPS Latitude Ref") => lat_ref = Some(value),
+                ("GPS", "GPS Longitude") => lon = Some(value),
+                ("GPS", "GPS Longitude Ref") => lon_ref = Some(value),
+                _ => {}
+            }
+        }
+
+        MediaInfo {
+            mime: mime.get(),
+            make: make.get(),
+            model: model.get(),
+            width: width.get(),
+            height: height.get(),
+            duration: duration.get(),
+            latitude: lat.and_then(|v| parse_degrees(v, lat_ref)),
+            longitude: lon.and_then(|v| parse_degrees(v, lon_ref)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_texts() {
+        let texts: Vec<String> = [
+            "[JPEG] Image Height = 400 pixels",
+            "[JPEG] Image Width = 600 pixels",
+            "[Exif Thumbnail] Image Width = 160 pixels",
+            "[Exif IFD0] Make = FUJIFILM",
+            "[Exif IFD0] Model = FinePixS1Pro",
+            "[ICC Profile] Device model = sRGB",
+            "[Exif SubIFD] Exif Image Width = 2400 pixels",
+            "[Exif SubIFD] Exif Image Height = 1600 pixels",
+            "[GPS] GPS Latitude Ref = N",
+            "[GPS] GPS Latitude = 54- 59' 22.8\"",
+            "[GPS] GPS Longitude Ref = W",
+            "[GPS] GPS Longitude = 1- 54' 51\"",
+            "[File Type] Detected MIME Type = image/jpeg",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let info = MediaInfo::from_texts(&texts);
+        println!("info: {:#?}", info);
+        assert_eq!(info.mime.as_deref(), Some("image/jpeg"));
+        assert_eq!(info.make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(info.model.as_deref(), Some("FinePixS1Pro"));
+        assert_eq!((info.width, info.height), (Some(600), Some(400)));
+        assert!(info.duration.is_none());
+        assert!((info.latitude.unwrap() - 54.9897).abs() < 0.0001);
+        assert!((info.longitude.unwrap() + 1.9142).abs() < 0.0001);
+
+        let texts: Vec<String> = [
+            "[QuickTime] Duration = 00:01:02.5",
+            "[QuickTime Video] Width = 1920 pixels",
+            "[QuickTime Video] Height = 1080 pixels",
+            "[MP4] Duration in Seconds = 62.5",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let info = MediaInfo::from_texts(&texts);
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.duration, Some(62.5));
+        assert!(info.latitude.is_none() && info.make.is_none());
+    }
+
+    #[test]
+    fn test_parse_degrees() {
+        assert_eq!(parse_degrees("-1- 30' 0\"", Some("W")), Some(-1.5));
+        assert_eq!(parse_degrees("10- 30' 0\"", Some("S")), Some(-10.5));
+        assert_eq!(parse_degrees("10.25", None), Some(10.25));
+        assert_eq!(parse_degrees("unknown", None), None);
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{delete_finfo, delete_index_not_algorithm, query_finfo_not_algorithm, update_hash};
+use super::target::OUTPUT_GEN;
+use utils::crypto::get_file_hashes;
+
+// 将数据库中使用其他算法计算的 hash 迁移到当前配置的算法
+// 读取归档文件，校验旧 hash 后计算新 hash 并更新，归档文件不存在或被修改过的记录无法迁移，prune 时删除这些记录
+pub fn do_migrate_hash(ctx: &Context, output: PathBuf, prune: bool) -> Result<()> {
+    let algorithm = ctx.hash_algorithm();
+    if !utils::crypto::is_supported_hash(algorithm) {
+        return Err(anyhow::anyhow!("unsupported hash algorithm: {}", algorithm));
+    }
+    let conn = ctx.conn()?.lock().unwrap();
+    let records = query_finfo_not_algorithm(&conn, algorithm)?;
+    let total = records.len();
+    info!(total, algorithm, output=?output, "start migrate hash");
+
+    let (mut migrated, mut failed) = (0, 0);
+    for (i, finfo) in records.iter().enumerate() {
+        let count = i + 1;
+        let file = OUTPUT_GEN(&output, &finfo.parts);
+        let reason = if file.is_file() {
+            let hashes = get_file_hashes(&[&finfo.algorithm, algorithm], &file)?;
+            if hashes[0] == finfo.hash {
+                update_hash(&conn, &finfo.hash, &hashes[1], algorithm)?;
+                info!(file=?file, from=%finfo.hash, to=%hashes[1], "✅ [{count}/{total}] success migrate hash");
+                migrated += 1;
+                continue;
+            }
+            "the archived file is modified"
+        } else {
+            "the archived file not found"
+        };
+        failed += 1;
+        if prune {
+            delete_finfo(&conn, &finfo.hash)?;
+            warn!(file=?file, hash=%finfo.hash, reason, "⚠️ [{count}/{total}] delete the record");
+        } else {
+            warn!(file=?file, hash=%finfo.hash, reason, "⚠️ [{count}/{total}] skip the record, use `--prune` to delete it");
+        }
+    }
+    // 源文件索引中的 hash 只是缓存，直接删除，下次运行时重新计算
+    let deleted = delete_index_not_algorithm(&conn, algorithm)?;
+    info!(
+        migrated,
+        failed,
+        index_deleted = deleted,
+        "migrate hash done"
+    );
+    if failed > 0 && !prune {
+        return Err(anyhow::anyhow!(
+            "{} records could not be migrated, check the archive or use `--prune`",
+            failed
+        ));
+    }
+    Ok(())
+}
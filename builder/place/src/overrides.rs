@@ -0,0 +1,429 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use rusqlite::Connection;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, finish_run, insert_action, insert_run, query_finfo, query_override,
+    update_earliest, upsert_override,
+};
+use super::relayout::{layout_parts, layout_target};
+use super::target::{OUTPUT_GEN, remove_empty_dirs, set_file_times};
+use utils::crypto::{get_file_hash, hash_hex_len};
+
+// 手动输入的日期按本地时间解析，只有年或年月时取第一天
+pub(crate) fn parse_manual_date(s: &str) -> Result<DateTime<Local>> {
+    let s = s.trim();
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| {
+            [s.to_string(), format!("{}-01", s), format!("{}-01-01", s)]
+                .iter()
+                .find_map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+        .ok_or(anyhow::anyhow!(
+            "invalid date {:?}, expected YYYY-MM-DD[ HH:MM:SS], YYYY-MM or YYYY",
+            s
+        ))?;
+    let earliest = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or(anyhow::anyhow!("invalid local time {:?}", s))?;
+    if earliest.timestamp() < 0 {
+        return Err(anyhow::anyhow!("the date {:?} is before 1970", s));
+    }
+    Ok(earliest)
+}
+
+// 参数可以是文件或者 hash，与 `origin` 一致，不存在的文件路径不会被当作 hash
+// hash 的长度需要与配置的算法一致，如 md5 和 xxh3 为 32，sha256 和 blake3 为 64
+pub(crate) fn resolve_hash(ctx: &Context, target: &str) -> Result<String> {
+    let algorithm = ctx.hash_algorithm();
+    if Path::new(target).is_file() {
+        return Ok(get_file_hash(algorithm, Path::new(target))?);
+    }
+    let hex = target.chars().all(|c| c.is_ascii_hexdigit());
+    match hash_hex_len(algorithm) {
+        Some(len) if hex && target.len() == len => Ok(target.to_lowercase()),
+        Some(len) if hex && !target.is_empty() => Err(anyhow::anyhow!(
+            "{:?} is not a file, and not a {} hash of {} hex digits",
+            target,
+            algorithm,
+            len
+        )),
+        _ => Err(anyhow::anyhow!(
+            "file {:?} not found, and it is not a hash",
+            target
+        )),
+    }
+}
+
+// 每行为 `<path|hash>,<date>`，路径中可能有逗号，按最后一个逗号分隔，忽略空行、注释和表头
+pub(crate) fn read_csv(path: &Path) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)?;
+    let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+    let mut entries = Vec::new();
+    // 表头是第一个非空、非注释的行
+    let mut first = true;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let header = std::mem::replace(&mut first, false);
+        let (target, date) = line.rsplit_once(',').ok_or(anyhow::anyhow!(
+            "invalid line {} in {:?}, expected `<path|hash>,<date>`",
+            i + 1,
+            path
+        ))?;
+        if header && unquote(date).eq_ignore_ascii_case("date") {
+            continue;
+        }
+        entries.push((unquote(target), unquote(date)));
+    }
+    Ok(entries)
+}
+
+// 保存手动指定的时间，之前的值记录在 previous 中，撤销时恢复
+fn record_override(
+    conn: &Connection,
+    run_id: i64,
+    hash: &str,
+    earliest: DateTime<Local>,
+) -> Result<()> {
+    let previous = query_override(conn, hash)?;
+    upsert_override(conn, hash, earliest.timestamp())?;
+    insert_action(
+        conn,
+        &ActionInfo {
+            run_id,
+            hash: hash.to_string(),
+            action: "override".to_string(),
+            timestamp: Local::now().timestamp(),
+            previous: previous.map(|p| p.to_string()),
+            ..Default::default()
+        },
+    )?;
+    Ok(())
+}
+
+// 按指定的时间把归档文件移动到新的年月目录，移动和时间的修改都记录在运行中，可以撤销
+pub(crate) fn assign(
+    conn: &Connection,
+    output: &Path,
+    run_id: i64,
+    finfo: &FileInfo<String>,
+    earliest: DateTime<Local>,
+) -> Result<PathBuf> {
+    let from = OUTPUT_GEN(output, &finfo.parts);
+    let mut target = layout_target(&from, finfo)?;
+    target.set_earliest_manual(earliest.timestamp() as u64);
+    // 新路径不带 `unknown-date/` 前缀，重名时追加序号
+    let mut parts = None;
+    for i in 0..1000 {
+        let p = layout_parts(&target, None, i, false)?;
+        if p == finfo.parts.as_ref() || !OUTPUT_GEN(output, &p).exists() {
+            parts = Some(p);
+            break;
+        }
+    }
+    let parts = parts.ok_or(anyhow::anyhow!("no available name for {:?}", from))?;
+    let to = OUTPUT_GEN(output, &parts);
+    let moved = to != from;
+    if moved {
+        if let Some(dir) = to.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::rename(&from, &to)?;
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    record_override(&tx, run_id, &finfo.hash, earliest)?;
+    update_earliest(&tx, &finfo.hash, &parts, earliest.timestamp(), false)?;
+    insert_action(
+        &tx,
+        &ActionInfo {
+            run_id,
+            hash: finfo.hash.to_string(),
+            source: moved.then(|| from.to_string_lossy().to_string()),
+            destination: Some(to.to_string_lossy().to_string()),
+            action: match moved {
+                true => "move",
+                false => "update",
+            }
+            .to_string(),
+            timestamp: Local::now().timestamp(),
+            previous: Some(serde_json::to_string(finfo)?),
+            ..Default::default()
+        },
+    )?;
+    if let Err(e) = tx.commit() {
+        // 数据库更新失败时移回原路径
+        if moved {
+            std::fs::rename(&to, &from)?;
+        }
+        return Err(e.into());
+    }
+    if moved {
+        remove_empty_dirs(&from, output);
+    }
+    if let Err(e) = set_file_times(&to, earliest) {
+        warn!(file=?to, error=%e, "⚠️ set file times failed");
+    }
+    info!(from=?from, to=?to, earliest=%earliest, "📅 assign the date");
+    Ok(to)
+}
+
+// 已归档的文件立即移动，未归档的只保存时间，之后 `place` 时使用
+fn set_date(
+    conn: &Connection,
+    output: &Path,
+    run_id: i64,
+    hash: &str,
+    earliest: DateTime<Local>,
+) -> Result<Option<(PathBuf, PathBuf)>> {
+    let Some(finfo) = query_finfo(conn, hash)? else {
+        let tx = conn.unchecked_transaction()?;
+        record_override(&tx, run_id, hash, earliest)?;
+        tx.commit()?;
+        return Ok(None);
+    };
+    let file = OUTPUT_GEN(output, &finfo.parts);
+    if !file.is_file() {
+        return Err(anyhow::anyhow!(
+            "the archived file {:?} not found, repair it by `verify --repair` first",
+            file
+        ));
+    }
+    let to = assign(conn, output, run_id, &finfo, earliest)?;
+    Ok(Some((file, to)))
+}
+
+/// the result of `place::set_date`, see `check` for the failed entries.
+#[derive(Debug, Clone, Default)]
+pub struct SetDateReport {
+    pub run: i64,
+    pub total: usize,
+    /// the archived files moved into the new directory, relative to the output
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// the hashes not archived yet and the dates, used when placed
+    pub pending: Vec<(String, DateTime<Local>)>,
+    pub failed: usize,
+}
+
+impl SetDateReport {
+    /// the lines printed by the `set-date` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .moved
+            .iter()
+            .map(|(from, to)| format!("{} -> {}", from.display(), to.display()))
+            .collect();
+        for (hash, earliest) in self.pending.iter() {
+            lines.push(format!(
+                "{} {} not archived, the date is used when placed",
+                hash,
+                earliest.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        lines.push(format!(
+            "{} of {} dates set, {} moved, {} pending, run {}",
+            self.moved.len() + self.pending.len(),
+            self.total,
+            self.moved.len(),
+            self.pending.len(),
+            self.run
+        ));
+        lines
+    }
+
+    /// the error if any date failed to set, the others are still set.
+    pub fn check(&self) -> Result<()> {
+        if self.failed > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} dates failed to set",
+                self.failed,
+                self.total
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 手动指定文件的时间，所有条目记录为一次运行，失败的条目不影响其他条目
+pub fn do_set_date(
+    ctx: &Context,
+    output: PathBuf,
+    entries: Vec<(String, String)>,
+    input: &str,
+) -> Result<SetDateReport> {
+    let total = entries.len();
+    let mut failed = 0;
+    // 先解析日期和计算 hash，不占用数据库
+    let mut resolved = Vec::new();
+    for (target, date) in entries {
+        match parse_manual_date(&date).and_then(|d| Ok((resolve_hash(ctx, &target)?, d))) {
+            Ok((hash, earliest)) => resolved.push((target, hash, earliest)),
+            Err(e) if total == 1 => return Err(e),
+            Err(e) => {
+                warn!(target, date, error=%e, "⚠️ set date failed");
+                failed += 1;
+            }
+        }
+    }
+    if resolved.is_empty() {
+        return Err(anyhow::anyhow!("no date is set, {} failed", failed));
+    }
+
+    let conn = ctx.conn()?.lock().unwrap();
+    let started = Local::now().timestamp();
+    let run = insert_run(&conn, started)?;
+    let relative = |f: &Path| f.strip_prefix(&output).unwrap_or(f).to_path_buf();
+    let mut report = SetDateReport {
+        run: run.id,
+        total,
+        ..Default::default()
+    };
+    for (target, hash, earliest) in resolved {
+        match set_date(&conn, &output, run.id, &hash, earliest) {
+            Ok(Some((from, to))) => report.moved.push((relative(&from), relative(&to))),
+            Ok(None) => report.pending.push((hash, earliest)),
+            Err(e) => {
+                warn!(target, hash, error=%e, "⚠️ set date failed");
+                failed += 1;
+            }
+        }
+    }
+    report.failed = failed;
+
+    finish_run(
+        &conn,
+        run,
+        &output,
+        json!({ "set-date": input }),
+        None,
+        json!({
+            "total": total,
+            "moved": report.moved.len(),
+            "pending": report.pending.len(),
+            "failed": failed
+        }),
+    )?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{insert_finfo, query_actions, query_finfo_undated};
+    use crate::target::UNKNOWN_DATE_DIR;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_parse_manual_date() {
+        let date = |s: &str| parse_manual_date(s).map(|d| d.format("%F %T").to_string());
+        assert_eq!(date("2002-11-05").unwrap(), "2002-11-05 00:00:00");
+        assert_eq!(date("2002-11-05 10:20:30").unwrap(), "2002-11-05 10:20:30");
+        assert_eq!(date("2002-11-05T10:20:30").unwrap(), "2002-11-05 10:20:30");
+        assert_eq!(date("2002-11").unwrap(), "2002-11-01 00:00:00");
+        assert_eq!(date("2002").unwrap(), "2002-01-01 00:00:00");
+        assert!(date("2002-13").is_err());
+        assert!(date("1960-01-01").is_err());
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let path = std::env::temp_dir().join("mmfplace_test_overrides.csv");
+        std::fs::write(
+            &path,
+            "# exported\n\npath,date\n# a comment\n/from/a, b.jpg,2002-11-05\n\"A18932E3\", \"2002-11\"\n",
+        )
+        .unwrap();
+        let entries = read_csv(&path).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("/from/a, b.jpg".to_string(), "2002-11-05".to_string()),
+                ("A18932E3".to_string(), "2002-11".to_string()),
+            ]
+        );
+        std::fs::write(&path, "no date\n").unwrap();
+        assert!(read_csv(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let md5 = "A18932E314DBB4C81C6FD0E282D81D16";
+        assert_eq!(resolve_hash(&ctx, md5).unwrap(), md5.to_lowercase());
+        assert!(resolve_hash(&ctx, "A18932E3").is_err());
+        assert!(resolve_hash(&ctx, "/not/exists.jpg").is_err());
+        let mut config = config::CONFIG.clone();
+        config.hash = Some("sha256".to_string());
+        let ctx = Context::new(config, ":memory:");
+        assert!(resolve_hash(&ctx, md5).is_err());
+        let sha256 = "0291b9bf797a3f59684c7e5817eb5b948796bc4271e004bc76515dabecadcee7";
+        assert_eq!(resolve_hash(&ctx, sha256).unwrap(), sha256);
+    }
+
+    #[test]
+    fn test_set_date() {
+        let output = std::env::temp_dir().join("mmfplace_test_overrides");
+        let _ = std::fs::remove_dir_all(&output);
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let parts = [UNKNOWN_DATE_DIR, "2020", "01", "a.jpg"].map(String::from);
+        let file = OUTPUT_GEN(&output, &parts);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, b"a").unwrap();
+        // the name is taken in the new directory
+        std::fs::create_dir_all(output.join("2002/11")).unwrap();
+        std::fs::write(output.join("2002/11/a.jpg"), b"x").unwrap();
+
+        let conn = ctx.conn().unwrap().lock().unwrap();
+        let finfo = FileInfo {
+            parts: Cow::Owned(parts.to_vec()),
+            hash: Cow::Owned("a".to_string()),
+            algorithm: Cow::Owned("md5".to_string()),
+            undated: true,
+            ..Default::default()
+        };
+        insert_finfo(&conn, &finfo).unwrap();
+        assert_eq!(query_finfo_undated(&conn).unwrap().len(), 1);
+
+        let earliest = parse_manual_date("2002-11-05").unwrap();
+        let (from, to) = set_date(&conn, &output, 1, "a", earliest).unwrap().unwrap();
+        assert_eq!(from, file);
+        assert_eq!(to, output.join("2002/11/a_01.jpg"));
+        assert!(to.is_file() && !file.exists());
+        assert!(!output.join(UNKNOWN_DATE_DIR).exists());
+        assert_eq!(
+            query_override(&conn, "a").unwrap(),
+            Some(earliest.timestamp())
+        );
+        let updated = query_finfo(&conn, "a").unwrap().unwrap();
+        assert_eq!(updated.parts.join("/"), "2002/11/a_01.jpg");
+        assert_eq!(updated.earliest, earliest.timestamp());
+        assert!(query_finfo_undated(&conn).unwrap().is_empty());
+
+        // not archived yet, only the override is saved
+        assert!(
+            set_date(&conn, &output, 1, "b", earliest)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            query_override(&conn, "b").unwrap(),
+            Some(earliest.timestamp())
+        );
+        let actions: Vec<String> = query_actions(&conn, 1)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.action)
+            .collect();
+        assert_eq!(actions, vec!["override", "move", "override"]);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use image::imageops::FilterType;
+use once_cell::sync::Lazy;
+use std::path::Path;
+
+/// the perceptual hashes of an image, used to find the near-duplicate images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageHash {
+    /// difference hash, compare the adjacent pixels of 9x8 grayscale
+    pub dhash: u64,
+    /// DCT hash, compare the low frequencies of 32x32 grayscale with median
+    pub phash: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+const DCT_SIZE: usize = 32;
+const HASH_SIZE: usize = 8;
+
+// DCT-II 系数表 cos((2x + 1) * u * PI / 2N)
+static DCT_TABLE: Lazy<Vec<f64>> = Lazy::new(|| {
+    let n = DCT_SIZE as f64;
+    (0..HASH_SIZE)
+        .flat_map(|u| {
+            (0..DCT_SIZE).map(move |x| {
+                ((2.0 * x as f64 + 1.0) * u as f64 * std::f64::consts::PI / (2.0 * n)).cos()
+            })
+        })
+        .collect()
+});
+
+// 解码失败说明不是支持的图片格式，返回 None
+pub fn image_hash(path: &Path) -> Result<Option<ImageHash>> {
+    let img = match image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+    {
+        Ok(img) => img,
+        Err(_) => return Ok(None),
+    };
+    let (width, height) = (img.width(), img.height());
+
+    let gray = img
+        .resize_exact(HASH_SIZE as u32 + 1, HASH_SIZE as u32, FilterType::Triangle)
+        .into_luma8();
+    let mut dhash = 0u64;
+    for y in 0..HASH_SIZE as u32 {
+        for x in 0..HASH_SIZE as u32 {
+            dhash <<= 1;
+            if gray.get_pixel(x, y)[0] > gray.get_pixel(x + 1, y)[0] {
+                dhash |= 1;
+            }
+        }
+    }
+
+    let gray = img
+        .resize_exact(DCT_SIZE as u32, DCT_SIZE as u32, FilterType::Triangle)
+        .into_luma8();
+    let pixels: Vec<f64> = gray.pixels().map(|p| p[0] as f64).collect();
+    // 只需要计算左上角 8x8 的低频部分
+    let mut rows = vec![0f64; DCT_SIZE * HASH_SIZE];
+    for y in 0..DCT_SIZE {
+        for u in 0..HASH_SIZE {
+            rows[y * HASH_SIZE + u] = (0..DCT_SIZE)
+                .map(|x| pixels[y * DCT_SIZE + x] * DCT_TABLE[u * DCT_SIZE + x])
+                .sum();
+        }
+    }
+    let mut coeffs = vec![0f64; HASH_SIZE * HASH_SIZE];
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            coeffs[v * HASH_SIZE + u] = (0..DCT_SIZE)
+                .map(|y| rows[y * HASH_SIZE + u] * DCT_TABLE[v * DCT_SIZE + y])
+                .sum();
+        }
+    }
+    // 直流分量不参与中位数计算
+    let mut sorted = coeffs[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    let phash = coeffs
+        .iter()
+        .fold(0u64, |h, c| (h << 1) | (*c > median) as u64);
+
+    Ok(Some(ImageHash {
+        dhash,
+        phash,
+        width,
+        height,
+    }))
+}
+
+/// the similarity of two hashes in [0, 1], 1 means the same.
+pub fn similarity(a: u64, b: u64) -> f64 {
+    1.0 - (a ^ b).count_ones() as f64 / 64.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn get_root() -> PathBuf {
+        PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .to_path_buf()
+    }
+
+    #[test]
+    fn test_image_hash() {
+        let tests = get_root().join("tests");
+        let jpg = image_hash(&tests.join("2002/11/simple.jpg"))
+            .unwrap()
+            .unwrap();
+        println!("jpg: {:#?}", jpg);
+        let img = image::open(tests.join("2002/11/simple.jpg")).unwrap();
+
+        // the same image re-saved as png
+        let png = std::env::temp_dir().join("mmfplace_test_image_hash.png");
+        img.save(&png).unwrap();
+        let resaved = image_hash(&png).unwrap().unwrap();
+        assert_eq!((resaved.width, resaved.height), (jpg.width, jpg.height));
+        assert!(similarity(jpg.phash, resaved.phash) > 0.95);
+
+        // a resized copy is still similar
+        img.resize(jpg.width / 2, jpg.height / 2, FilterType::Lanczos3)
+            .save(&png)
+            .unwrap();
+        let small = image_hash(&png).unwrap().unwrap();
+        std::fs::remove_file(&png).unwrap();
+        assert!(small.width < jpg.width);
+        assert!(similarity(jpg.phash, small.phash) > 0.9);
+        assert!(similarity(jpg.dhash, small.dhash) > 0.9);
+
+        // not an image
+        assert!(image_hash(&get_root().join("README.md")).unwrap().is_none());
+    }
+}
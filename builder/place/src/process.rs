@@ -1,81 +1,296 @@
 use anyhow::Result;
 use chrono::Datelike;
-use once_cell::sync::OnceCell;
+use futures::channel::mpsc::UnboundedSender;
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::json;
 use std::borrow::Cow;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::Semaphore;
 use tokio::sync::mpsc;
 use tracing::{debug, debug_span, error, info, warn};
 use tracing_futures::Instrument;
-use walkdir::WalkDir;
 
-use super::db::{FileInfo, get_connection, insert_finfo, query_finfo, update_finfo};
-use super::target::{OUTPUT_GEN, Target};
+use super::analyze::FileReport;
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, RunInfo, RunRecord, SourceIndex, SourceInfo, insert_action, insert_finfo,
+    insert_run, query_actions, query_finfo, query_finfo_not_algorithm, query_index, query_override,
+    touch_finfo, update_finfo, update_run, upsert_index, upsert_source,
+};
+use super::input::{Inputs, WalkErrors};
+use super::job::{PlaceEvent, PlaceOptions};
+use super::media::MediaInfo;
+use super::progress::{Phase, Tracker};
+use super::summary::{FailedFile, RunSummary};
+use super::target::{
+    ConflictPolicy, Copied, OUTPUT_GEN, Target, UNKNOWN_DATE_DIR, apply_conflict_policy,
+    move_to_trash,
+};
 
-use config::CONFIG;
 use tools::metadata_extractor;
+use utils::crypto::get_file_hash;
 
-fn error_with_exit() -> ! {
-    std::process::exit(1);
-}
-
-// 临时共享数据，我不知道该取什么名字hhh...
-#[derive(Debug, Clone, Default)]
-struct TempData {
-    input: PathBuf,
+// 一次运行的上下文和参数，在解析和归档的任务之间共享
+#[derive(Debug)]
+struct Job {
+    ctx: Context,
+    inputs: Inputs,
     output: PathBuf,
     test: bool,
     rename: bool,
+    // 没有元数据时间的文件放到 `unknown-date/` 中
+    unknown_date: bool,
+    rehash: bool,
     total: usize,
+    // the current run, the id is 0 in test mode
+    run: RunInfo,
+    // 库调用时发送每个文件的处理结果，接收端关闭时忽略
+    events: Option<UnboundedSender<PlaceEvent>>,
+    progress: Tracker,
+    // 处理失败的文件，在最后汇总
+    failed: Mutex<Vec<FailedFile>>,
 }
 
-static TEMPDATA: OnceCell<TempData> = OnceCell::new();
-
-fn temp_init(input: PathBuf, output: PathBuf, test: bool, rename: bool, total: usize) {
-    TEMPDATA
-        .set(TempData {
-            input,
-            output,
-            test,
-            rename,
-            total,
-        })
-        .expect("TempData is already initialized")
+impl Job {
+    fn send(&self, event: PlaceEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.unbounded_send(event);
+        }
+    }
+
+    // 单个文件失败不影响其他文件的处理，phase 是文件失败时所处的步骤
+    fn fail(&self, path: &Path, phase: Phase, error: anyhow::Error) {
+        let failed = FailedFile {
+            path: path.to_path_buf(),
+            error: format!("{:#}", error),
+        };
+        self.progress.failed(phase);
+        self.failed.lock().unwrap().push(failed.clone());
+        self.send(PlaceEvent::Failed(failed));
+    }
 }
-fn temp_get() -> &'static TempData {
-    TEMPDATA.get().expect("TempData is not initialized")
+
+/// what is done to a file by the place job.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placed {
+    /// a new file, recorded and copied
+    Created,
+    /// an earlier file with the same content, replaced the archived one
+    Replaced,
+    /// the archived file was missing or modified, copied again
+    Restored,
+    /// already archived
+    Skipped,
+    /// test mode, the output path is generated but nothing is copied or recorded
+    Planned,
 }
 
-pub async fn do_process(
-    input: PathBuf,
-    output: PathBuf,
-    test: bool,
-    rename_with_ymd: bool,
+/// the counts of a run, saved in the runs table.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunCounts {
+    pub total: usize,
+    pub created: usize,
+    pub replaced: usize,
+    pub restored: usize,
+    pub skipped: usize,
+    /// the files would be placed in test mode
+    pub planned: usize,
+    /// the modified archived files, see `ConflictPolicy`
+    pub conflicts: usize,
+    /// the files failed to parse or place
+    pub failed: usize,
+}
+
+impl RunCounts {
+    pub(crate) fn add(&mut self, placed: Placed) {
+        match placed {
+            Placed::Created => self.created += 1,
+            Placed::Replaced => self.replaced += 1,
+            Placed::Restored => self.restored += 1,
+            Placed::Skipped => self.skipped += 1,
+            Placed::Planned => self.planned += 1,
+        }
+    }
+}
+
+// 本次运行对归档文件和数据库的修改
+fn action_info(
+    job: &Job,
+    target: &Target,
+    action: &str,
+    source: &Path,
+    destination: &Path,
+) -> ActionInfo {
+    ActionInfo {
+        run_id: job.run.id,
+        hash: target.hash.clone(),
+        source: Some(source.to_string_lossy().to_string()),
+        destination: Some(destination.to_string_lossy().to_string()),
+        action: action.to_string(),
+        timestamp: chrono::Local::now().timestamp(),
+        ..Default::default()
+    }
+}
+
+fn record_action(
+    job: &Job,
+    conn: &Connection,
+    target: &Target,
+    action: &str,
+    source: &Path,
+    destination: &Path,
 ) -> Result<()> {
-    // let (input, output, test) = (&temp_get().input, &temp_get().output, temp_get().test);
-    let total = walkdir::WalkDir::new(&input)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_file())
-        .count();
-    temp_init(input, output, test, rename_with_ymd, total);
-    // add MMFPLACE_JAVA to env used by tools
-    if let Some(java) = config::CONFIG.java.as_ref() {
-        debug!(java, "set java environment variable");
-        unsafe {
-            std::env::set_var("MMFPLACE_JAVA", java);
+    insert_action(conn, &action_info(job, target, action, source, destination))?;
+    Ok(())
+}
+
+// 归档文件与记录的 hash 不同，说明被修改过，按冲突策略处理并记录，移动后的路径用于撤销时恢复
+fn resolve_conflict(
+    job: &Job,
+    conn: &Connection,
+    target: &Target,
+    file: &Path,
+) -> Result<ConflictPolicy> {
+    let policy = job.ctx.conflict_policy();
+    let moved = apply_conflict_policy(file, &job.output, job.run.id, policy)?;
+    let action = ActionInfo {
+        trash: moved.map(|m| m.to_string_lossy().to_string()),
+        ..action_info(
+            job,
+            target,
+            &format!("conflict-{}", policy),
+            &target.path,
+            file,
+        )
+    };
+    insert_action(conn, &action)?;
+    Ok(policy)
+}
+
+// 拷贝到归档目录并记录
+// 实际拷贝的字节数，归档文件已存在且相同或者按冲突策略跳过时为 0
+fn copied_bytes(target: &Target, copied: Copied) -> u64 {
+    match copied {
+        Copied::Skipped => 0,
+        _ => std::fs::metadata(&target.output).map_or(0, |m| m.len()),
+    }
+}
+
+fn copy_and_record(job: &Job, conn: &Connection, target: &Target) -> Result<Copied> {
+    let output = &target.output;
+    if output.is_file()
+        && get_file_hash(&target.algorithm, output)? != target.hash
+        && resolve_conflict(job, conn, target, output)? == ConflictPolicy::Skip
+    {
+        return Ok(Copied::Skipped);
+    }
+    let copied = target.copy_with_times()?;
+    if copied != Copied::Skipped {
+        record_action(job, conn, target, "copy", &target.path, output)?;
+    }
+    Ok(copied)
+}
+
+// 运行的输入、输出和参数，保存到 runs 表
+fn run_record(job: &Job, ended: Option<i64>, counts: Option<&RunCounts>) -> RunRecord {
+    let (data, run) = (job, job.run);
+    let input = match &data.inputs {
+        Inputs::Roots { roots, .. } => json!(roots),
+        Inputs::Files(files) => json!({ "files": files.len() }),
+    };
+    RunRecord {
+        id: run.id,
+        started: run.started,
+        ended,
+        input: Some(input.to_string()),
+        output: Some(data.output.to_string_lossy().to_string()),
+        flags: Some(
+            json!({ "rename_with_ymd": data.rename, "rehash": data.rehash, "unknown_date": data.unknown_date })
+                .to_string(),
+        ),
+        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        counts: counts.map(|c| json!(c).to_string()),
+        ..Default::default()
+    }
+}
+
+pub async fn do_process(
+    ctx: &Context,
+    options: PlaceOptions,
+    events: Option<UnboundedSender<PlaceEvent>>,
+) -> Result<RunSummary> {
+    let PlaceOptions {
+        inputs,
+        output,
+        test,
+        rename_with_ymd,
+        rehash,
+        unknown_date,
+        progress,
+    } = options;
+    // 多个输入共享同一个计数器和数据库连接，跨目录的相同 hash 文件按照同样的规则去重
+    let progress = Tracker::new(progress);
+    progress.notify(true);
+    let (total, bytes) = inputs.files(None).fold((0, 0), |(n, b), f| {
+        (n + 1, b + std::fs::metadata(&f).map_or(0, |m| m.len()))
+    });
+    progress.scanned(total, bytes);
+    let started = chrono::Local::now().timestamp();
+    let instant = std::time::Instant::now();
+    // 数据库中存在其他算法计算的 hash 时，无法正确去重，需要先执行 migrate-hash 迁移
+    if !test {
+        let algorithm = ctx.hash_algorithm();
+        if !utils::crypto::is_supported_hash(algorithm) {
+            return Err(anyhow::anyhow!("unsupported hash algorithm: {}", algorithm));
+        }
+        if let Some(conflict) = ctx.config().conflict.as_deref() {
+            conflict.parse::<ConflictPolicy>()?;
+        }
+        let conn = ctx.conn()?.lock().unwrap();
+        let others = query_finfo_not_algorithm(&conn, algorithm)?.len();
+        if others > 0 {
+            return Err(anyhow::anyhow!(
+                "{} records in database are hashed by other algorithm, run `mmfplace migrate-hash` with the output to migrate them to {}",
+                others,
+                algorithm
+            ));
         }
     }
-    let (input, output, test) = (&temp_get().input, &temp_get().output, temp_get().test);
-    info!(input=?input, total=total, output=?output, test=test, "start process");
+    let run = if test {
+        RunInfo { id: 0, started }
+    } else {
+        insert_run(&ctx.conn()?.lock().unwrap(), started)?
+    };
+    let job = Arc::new(Job {
+        ctx: ctx.clone(),
+        inputs,
+        output,
+        test,
+        rename: rename_with_ymd,
+        unknown_date,
+        rehash,
+        total,
+        run,
+        events,
+        progress,
+        failed: Mutex::new(Vec::new()),
+    });
+    job.send(PlaceEvent::Started { run: run.id, total });
+    if !test {
+        update_run(&ctx.conn()?.lock().unwrap(), &run_record(&job, None, None))?;
+    }
+    info!(inputs=?job.inputs, total=total, output=?job.output, test=test, rehash=rehash, "start process");
 
     // MPSC mode
-    let concurrency: usize = CONFIG.batch.unwrap() as usize;
+    let concurrency: usize = ctx.batch();
     let channel_size: usize = 100;
     let (tx, mut rx) = mpsc::channel::<Target>(channel_size);
     let processed_count = Arc::new(AtomicUsize::new(0));
+    let walk_errors = WalkErrors::default();
     let semaphore = Arc::new(Semaphore::new(concurrency));
 
     let root_span = debug_span!("process");
@@ -84,59 +299,77 @@ pub async fn do_process(
     let consumer = tokio::spawn({
         let processed_count = Arc::clone(&processed_count);
         let root_span = root_span.clone();
+        let job = Arc::clone(&job);
         async move {
-            while let Some(fdt) = rx.recv().await {
+            let mut summary = RunSummary::default();
+            while let Some(mut fdt) = rx.recv().await {
                 let span = debug_span!("task_place", file = ?fdt.path);
                 async {
-                    if let Err(e) = do_place(fdt, &processed_count).await {
-                        error!(error=%e, "place error");
-                        error_with_exit();
+                    match do_place(&job, &mut fdt, &processed_count).await {
+                        Ok((placed, copied)) => {
+                            let size = std::fs::metadata(&fdt.path).map_or(0, |m| m.len());
+                            let year = fdt.get_earliest().ok().map(|e| e.year());
+                            // 已处理过的文件没有解析元数据，不计入
+                            let filetime_only = !fdt.dealt && fdt.get_parsedtime().is_empty();
+                            summary.add(placed, year, filetime_only, copied);
+                            job.progress.placed(size);
+                            let file = Box::new(FileReport::from(&fdt));
+                            job.send(PlaceEvent::Placed { file, placed });
+                        }
+                        Err(e) => {
+                            error!(file=?fdt.path, error=%e, "place error");
+                            job.fail(&fdt.path, Phase::Placing, e);
+                        }
                     }
                 }
                 .instrument(span)
                 .await;
             }
             info!("finished consumer");
+            summary
         }
         .instrument(root_span)
     });
 
     let producer = tokio::spawn({
-        // let input = temp_get().input.clone();
         let tx = tx; // tx.clone();
         let semaphore = Arc::clone(&semaphore);
         let root_span = root_span.clone();
+        let walk_errors = Arc::clone(&walk_errors);
+        let job = Arc::clone(&job);
 
         async move {
             let mut tasks = Vec::new();
-            for entry in WalkDir::new(input)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.file_type().is_file())
-            {
-                let path = entry.path().to_path_buf();
+            for path in job.inputs.files(Some(walk_errors)) {
                 let tx = tx.clone();
                 let semaphore = Arc::clone(&semaphore);
                 let root_span = root_span.clone();
+                let job = Arc::clone(&job);
 
                 let task = tokio::spawn(
                     async move {
                         let span = debug_span!("task_parse", file = ?path);
                         async {
                             let _permit = semaphore.acquire().await.unwrap();
-                            match do_parse(path).await {
+                            let parsed = match hash_target(&job, path.clone()) {
+                                Ok(t) => do_parse(&job, t).await.map_err(|e| (Phase::Extracting, e)),
+                                Err(e) => Err((Phase::Hashing, e)),
+                            };
+                            match parsed {
                                 Ok(t) => {
-                                    if let Err(e) = tx.send(t).await {
-                                        error!("send task error: {:#?}", e);
-                                        error_with_exit();
+                                    // 只有归档任务异常退出时才会发送失败
+                                    if tx.send(t).await.is_err() {
+                                        error!(file=?path, "send task error, the consumer is stopped");
+                                        return Err(anyhow::anyhow!("send task {:?} error", path));
                                     }
                                 }
-                                Err(e) => {
-                                    error!(error=%e, "parse error");
-                                    error_with_exit();
+                                Err((phase, e)) => {
+                                    error!(file=?path, error=%e, "parse error");
+                                    job.fail(&path, phase, e);
                                 }
                             }
                             // drop(_permit);
+                            Ok(())
                         }
                         .instrument(span)
                         .await
@@ -147,55 +380,173 @@ pub async fn do_process(
                 tasks.push(task);
 
                 if tasks.len() >= channel_size {
-                    let _ = futures::future::join_all(tasks).await;
+                    for r in futures::future::join_all(tasks).await {
+                        r??;
+                    }
                     tasks = Vec::new();
                 }
             }
-            futures::future::join_all(tasks).await;
+            for r in futures::future::join_all(tasks).await {
+                r??;
+            }
             // drop(tx);
             info!("finished producer");
+            anyhow::Ok(())
         }
     });
 
     // producer.await?;
     // consumer.await?;
-    let _ = tokio::join!(producer, consumer);
+    let (produced, summary) = tokio::join!(producer, consumer);
+    let mut summary = summary?;
+    produced??;
+    let failed = std::mem::take(&mut *job.failed.lock().unwrap());
+    let counts = &mut summary.counts;
+    counts.total = total;
+    counts.failed = failed.len();
+    if !test {
+        // 所有冲突都需要在最后汇总提示
+        let conflicts: Vec<ActionInfo> = query_actions(&ctx.conn()?.lock().unwrap(), run.id)?
+            .into_iter()
+            .filter(|a| a.action.starts_with("conflict-"))
+            .collect();
+        counts.conflicts = conflicts.len();
+        if !conflicts.is_empty() {
+            warn!(
+                count = conflicts.len(),
+                "⚠️ some archived files were modified, see `mmfplace history --run {}`", run.id
+            );
+            for c in conflicts.iter() {
+                warn!(
+                    file = c.destination,
+                    moved = c.trash,
+                    action = c.action,
+                    "conflict"
+                );
+            }
+        }
+        let record = run_record(&job, Some(chrono::Local::now().timestamp()), Some(counts));
+        update_run(&ctx.conn()?.lock().unwrap(), &record)?;
+        info!(run = record.id, counts = ?counts, "run recorded");
+    }
+
+    if !failed.is_empty() {
+        warn!(count = failed.len(), "⚠️ some files failed to place");
+    }
 
+    // 遍历过程中出错的文件/目录没有被处理，需要在最后汇总提示
+    let walk_errors = walk_errors.lock().unwrap();
+    if !walk_errors.is_empty() {
+        warn!(
+            count = walk_errors.len(),
+            "⚠️ some files/directories were not processed because of walk errors"
+        );
+        for e in walk_errors.iter() {
+            warn!(error = e, "walk error");
+        }
+    }
+
+    let summary = RunSummary {
+        run: run.id,
+        test,
+        output: job.output.clone(),
+        started,
+        ended: chrono::Local::now().timestamp(),
+        elapsed: instant.elapsed().as_secs_f64(),
+        failed,
+        walk_errors: walk_errors.clone(),
+        ..summary
+    };
+    // test mode 不写入输出目录
+    if !test {
+        let path = summary.write()?;
+        info!(file=?path, "summary saved");
+    }
+    job.progress.finish();
+    job.send(PlaceEvent::Finished(Box::new(summary.clone())));
     info!("all done");
-    Ok(())
+    Ok(summary)
 }
 
 // 计算文件hash -> 判断hash是否在数据库中 -> 存在 -> 获取parts部分拼接路径是否存在 -> 存在跳过/不存在拷贝
 //                                      -> 不存在 -> 解析所有时间(元数据+文件属性) -> 取最早 -> 插入数据库 -> 拷贝文件
-async fn do_parse(path: PathBuf) -> Result<Target> {
+// 根据源文件索引 (path, size, mtime, inode, dev) 判断文件是否变化，未变化则直接使用索引中的 hash
+fn new_target_with_index(job: &Job, path: PathBuf) -> Result<Target> {
+    let algorithm = job.ctx.hash_algorithm();
+    let mut index = SourceIndex::stat(&path)?;
+    index.algorithm = algorithm.to_string();
+    if !job.rehash {
+        let conn = job.ctx.conn()?.lock().unwrap();
+        if let Some(found) = query_index(&conn, &index.path)?
+            && found.unchanged(&index)
+        {
+            debug!(file=?path, hash=found.hash, "💡 the file is unchanged, use the indexed hash");
+            let mut target = Target::with_hash(path, found.hash, algorithm)?;
+            target.indexed = true;
+            return Ok(target);
+        }
+    }
+    let target = Target::new(path, algorithm)?;
+    index.hash = target.hash.clone();
+    upsert_index(&job.ctx.conn()?.lock().unwrap(), &index)?;
+    Ok(target)
+}
+
+fn hash_target(job: &Job, path: PathBuf) -> Result<Target> {
     debug!(file=?path, "🚀 begin parse file");
-    let mut target = Target::new(path)?;
+    // test mode 不读写数据库
+    let target = if job.test {
+        Target::new(path, job.ctx.hash_algorithm())?
+    } else {
+        new_target_with_index(job, path)?
+    };
+    job.progress.hashed();
+    Ok(target)
+}
 
+async fn do_parse(job: &Job, mut target: Target) -> Result<Target> {
+    // 手动指定的时间，见 `set-date`
+    let mut manual = None;
     // if test mode, don't check exists
-    if temp_get().test {
+    if job.test {
         debug!(file=?target.path, "💡 test mode, skip exists check");
     } else {
-        let conn = get_connection().lock().unwrap();
+        let conn = job.ctx.conn()?.lock().unwrap();
         if let Some(history) = query_finfo(&conn, &target.hash)? {
             target.dealt = true;
             target.set_parts(Some(history.parts.into()));
             // 更新 earliest，后边需要设置文件属性时间
             target.set_earliest(Some(history.earliest as u64))?;
+        } else {
+            manual = query_override(&conn, &target.hash)?;
         }
     }
 
     // 如果查到，说明之前已处理过了，则不再进行元数据解析
     if target.dealt {
         debug!(file = ?target.path, "file is already dealt before");
+        job.progress.extracted();
         return Ok(target);
     }
+    // 仍然需要解析文件类型和媒体信息，时间使用手动指定的
+    parse_metadata(&job.ctx, &mut target).await?;
+    if let Some(earliest) = manual {
+        info!(file=?target.path, earliest, "💡 use the date assigned by hand");
+        target.set_earliest_manual(earliest as u64);
+    }
+    job.progress.extracted();
+    Ok(target)
+}
 
+// 解析元数据中的文件类型、时间和媒体信息，并设置 earliest
+pub(crate) async fn parse_metadata(ctx: &Context, target: &mut Target) -> Result<()> {
+    let config = ctx.config();
     // 是否需要获取文件类型
-    let captype = CONFIG
+    let captype = config
         .typeregex
         .ignore
         .as_ref()
-        .map_or(true, |ignore| !ignore.contains(&target.extension));
+        .is_none_or(|ignore| !ignore.contains(&target.extension));
     // 如果需要忽略，则设置type字段，后边逻辑将跳过获取文件类型
     if !captype {
         debug!(file = ?target.path, "💡 the file type is ignored");
@@ -203,10 +554,11 @@ async fn do_parse(path: PathBuf) -> Result<Target> {
     }
 
     // 获取文件元数据并解析出所有时间格式
-    let texts = metadata_extractor(&target.path).await?;
+    let texts = metadata_extractor(ctx.java(), &target.path).await?;
+    target.media = MediaInfo::from_texts(&texts);
     'outer: for text in texts.iter() {
         // 过滤字符串
-        if let Some(ignore) = &CONFIG.dateregex.ignore {
+        if let Some(ignore) = &config.dateregex.ignore {
             for black in ignore {
                 if text.contains(black) {
                     debug!(black = black, text = text, "skip black string");
@@ -217,7 +569,7 @@ async fn do_parse(path: PathBuf) -> Result<Target> {
 
         // 获取文件type
         if target.ftype.is_none() {
-            for capture in &CONFIG.typeregex.list {
+            for capture in &config.typeregex.list {
                 if let Ok(t) = capture.capture(text) {
                     info!(
                         text = text,
@@ -231,29 +583,56 @@ async fn do_parse(path: PathBuf) -> Result<Target> {
         }
 
         // 获取文件时间
-        if let Ok(dt) = dateparser::parse(text) {
+        if let Ok(dt) = dateparser::parse_with_formats(text, &config.dateparse.list) {
             if dt.year() < 1975 {
                 warn!(file=?target.path, datetime=%dt, "💡 skip the datetime < 1975");
             } else {
                 info!(text = text, datetime = %dt, "🎉 success parse datetime from text");
-                target.add_parsedtime(dt);
+                target.add_parsedtime(dt, text);
             }
         }
     }
     target.set_earliest(None)?;
+    Ok(())
+}
 
-    Ok(target)
+// 新文件的归档路径，没有元数据时间的文件按选项放到 `unknown-date/` 中待确认
+fn set_output_parts(job: &Job, target: &mut Target) -> Result<()> {
+    let undated = target.is_undated();
+    if undated {
+        info!(file=?target.path, "💡 no datetime in metadata, add the file to the review queue");
+    }
+    let prefix = (job.unknown_date && undated).then_some(UNKNOWN_DATE_DIR);
+    target.set_output_parts_in(&job.output, job.rename, prefix)
 }
 
-async fn do_place(mut target: Target, processed_count: &Arc<AtomicUsize>) -> Result<()> {
+// 返回处理的结果和实际拷贝的字节数
+async fn do_place(
+    job: &Job,
+    target: &mut Target,
+    processed_count: &Arc<AtomicUsize>,
+) -> Result<(Placed, u64)> {
     let count = processed_count.fetch_add(1, Ordering::SeqCst) + 1;
-    let total = temp_get().total;
+    let total = job.total;
     debug!(file=?target.path, "🚀 begin place {} file", count);
 
-    if temp_get().test {
-        target.set_output_parts(&temp_get().output, temp_get().rename)?;
+    if job.test {
+        set_output_parts(job, target)?;
         info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success test finish");
-        return Ok(());
+        return Ok((Placed::Planned, 0));
+    }
+
+    // 记录源文件路径，相同 hash 的所有副本都会记录
+    {
+        let stat = SourceIndex::stat(&target.path)?;
+        let source = SourceInfo {
+            hash: target.hash.clone(),
+            path: stat.path,
+            size: stat.size,
+            mtime: stat.mtime,
+            run_id: job.run.id,
+        };
+        upsert_source(&job.ctx.conn()?.lock().unwrap(), &source)?;
     }
 
     // 在解析阶段，如果在数据库中找打同 hash，说明之前处理过了，会标记字段 dealt=true，并使用处理过的 parts 作为路径
@@ -262,18 +641,29 @@ async fn do_place(mut target: Target, processed_count: &Arc<AtomicUsize>) -> Res
 
     // 没有走 parse 流程，使用的历史 parts, 数据库不需要处理，直接拷贝即可
     if target.dealt {
-        // // TODO: 如果之前没有设置 `rename_with_ymd` 现在设置了，则是否需要将原来的路径删掉使用新路径？
+        // 已归档的路径不会随 `rename_with_ymd` 变化，需要通过 `relayout` 重新整理
         // let parts = target.get_parts()?;
         // let earliest = target.get_earliest()?;
         // 设置 output, parts 和 earliest 在 parsed 阶段设置
-        target.output = OUTPUT_GEN(&temp_get().output, target.get_parts()?);
-        target.copy_with_times()?;
+        target.output = OUTPUT_GEN(&job.output, target.get_parts()?);
+        let conn = job.ctx.conn()?.lock().unwrap();
+        touch_finfo(&conn, &target.hash, job.run.started)?;
+        // 源文件未变化且归档文件存在，直接跳过，不再校验归档文件的 hash
+        if target.indexed && target.output.is_file() {
+            info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success skip unchanged file");
+            return Ok((Placed::Skipped, 0));
+        }
+        let copied = copy_and_record(job, &conn, target)?;
         info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success place with history parsed finish");
-        return Ok(());
+        let placed = match copied {
+            Copied::Skipped => Placed::Skipped,
+            _ => Placed::Restored,
+        };
+        return Ok((placed, copied_bytes(target, copied)));
     }
 
     // 尝试最大 1000 次 来设置 parts 和 output
-    target.set_output_parts(&temp_get().output, temp_get().rename)?;
+    set_output_parts(job, target)?;
 
     // 处理并发中可能存在同 hash
     {
@@ -281,9 +671,16 @@ async fn do_place(mut target: Target, processed_count: &Arc<AtomicUsize>) -> Res
         let finfo = FileInfo {
             parts: Cow::Borrowed(parts),
             hash: Cow::Borrowed(&target.hash),
+            algorithm: Cow::Borrowed(&target.algorithm),
             earliest: target.get_earliest()?.timestamp(),
+            size: Some(std::fs::metadata(&target.path)?.len() as i64),
+            source: Some(target.path.to_string_lossy()),
+            first_seen: Some(job.run.started),
+            last_seen: Some(job.run.started),
+            media: target.media.clone(),
+            undated: target.is_undated(),
         };
-        let conn = get_connection().lock().unwrap();
+        let conn = job.ctx.conn()?.lock().unwrap();
         // 先查是否存在
         let find = query_finfo(&conn, &target.hash)?;
         if find.is_none() {
@@ -296,29 +693,46 @@ async fn do_place(mut target: Target, processed_count: &Arc<AtomicUsize>) -> Res
                     e
                 )
             })?;
+            record_action(job, &conn, target, "insert", &target.path, &target.output)?;
             // parts 和 earliest 在 parsed 阶段设置, output 在上边设置
-            target.copy_with_times()?;
+            let copied = copy_and_record(job, &conn, target)?;
             info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success place with new parsed finish");
-            return Ok(());
+            return Ok((Placed::Created, copied_bytes(target, copied)));
         }
 
         let history = find.unwrap();
         info!(current=?parts, history=?history.parts, "same hash file found, compare the time and overwrite it");
-        let history_file = OUTPUT_GEN(&temp_get().output, &history.parts.to_vec());
+        let history_file = OUTPUT_GEN(&job.output, &history.parts);
         // 如果已经存在了，比对 eraiest time，如果当前的更早，则更新，否则直接丢弃
         if finfo.earliest < history.earliest {
-            // 删除原来的文件
+            // 删除原来的文件，移到回收站，撤销时可以恢复，被修改过的按冲突策略处理
             if history_file.is_file() {
-                std::fs::remove_file(&history_file)?;
+                if get_file_hash(&target.algorithm, &history_file)? == target.hash {
+                    let trash = move_to_trash(&history_file, &job.output, job.run.id)?;
+                    let action = ActionInfo {
+                        trash: Some(trash.to_string_lossy().to_string()),
+                        ..action_info(job, target, "delete", &history_file, &history_file)
+                    };
+                    insert_action(&conn, &action)?;
+                } else {
+                    resolve_conflict(job, &conn, target, &history_file)?;
+                }
             }
-            // 更新数据库
+            // 更新数据库，记录更新前的数据
             update_finfo(&conn, &finfo)?;
+            let action = ActionInfo {
+                previous: Some(serde_json::to_string(&history)?),
+                ..action_info(job, target, "update", &target.path, &target.output)
+            };
+            insert_action(&conn, &action)?;
             // parts 和 earliest 在 parsed 阶段设置, output 在上边设置
-            target.copy_with_times()?;
+            let copied = copy_and_record(job, &conn, target)?;
             info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success place (<history) update finish");
+            return Ok((Placed::Replaced, copied_bytes(target, copied)));
         }
         // 时间晚，则丢弃
         else {
+            touch_finfo(&conn, &target.hash, job.run.started)?;
             // 检查下原始文件是否存在，如果不存在，则需要复制过去
             if !history_file.is_file() {
                 warn!(file=?history_file, "⚠️ history file not exists, restore it");
@@ -327,12 +741,17 @@ async fn do_place(mut target: Target, processed_count: &Arc<AtomicUsize>) -> Res
                 // 设置 earliest
                 target.set_earliest(Some(history.earliest as u64))?;
                 //  earliest 和 output 在上边设置， parts 用不到(此时parts为当前处理的文件，而非history)
-                target.copy_with_times()?;
+                let copied = copy_and_record(job, &conn, target)?;
+                info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success place (>=history) restore finish");
+                return Ok((Placed::Restored, copied_bytes(target, copied)));
             }
+            // 报告中使用已归档的路径和时间
+            target.output = history_file;
+            target.set_earliest(Some(history.earliest as u64))?;
             info!(from=?target.path, to=?target.output, "✅ [{count}/{total}] success place (>=history) finish");
         }
     }
-    Ok(())
+    Ok((Placed::Skipped, 0))
 }
 
 #[cfg(test)]
@@ -355,8 +774,23 @@ mod tests {
         let tests = get_root().join("tests");
         let input = tests.join("2002/11/simple.png");
         let output = get_root().join("tests");
-        temp_init(input.clone(), output.clone(), true, false, 1);
-        let mut target = do_parse(input.clone()).await.unwrap();
+        let job = Job {
+            ctx: Context::new(config::CONFIG.clone(), ":memory:"),
+            inputs: Inputs::Files(vec![input.clone()]),
+            output: output.clone(),
+            test: true,
+            rename: false,
+            unknown_date: false,
+            rehash: false,
+            total: 1,
+            run: RunInfo::default(),
+            events: None,
+            progress: Tracker::default(),
+            failed: Mutex::new(Vec::new()),
+        };
+        let mut target = do_parse(&job, hash_target(&job, input.clone()).unwrap())
+            .await
+            .unwrap();
         println!("target: {:#?}", target);
         assert_eq!("simple", target.name);
         assert_eq!("png", target.extension);
@@ -382,7 +816,9 @@ mod tests {
         let dup_file = input.with_file_name("simple_01.jpg");
         std::fs::copy(&input, &dup_file).unwrap();
         let input = tests.join("2002/11/simple.jpg");
-        let mut target = do_parse(input.clone()).await.unwrap();
+        let mut target = do_parse(&job, hash_target(&job, input.clone()).unwrap())
+            .await
+            .unwrap();
         println!("new target: {:#?}", target);
         assert_eq!(target.hash, "a18932e314dbb4c81c6fd0e282d81d16");
         assert_eq!("simple", target.name);
@@ -0,0 +1,327 @@
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// 回调的最小间隔，避免每个文件都刷新
+const INTERVAL: Duration = Duration::from_millis(200);
+
+/// the phase of a place job, the earliest step which is not done by all the files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// walking the inputs to count the files and bytes
+    #[default]
+    Scanning,
+    Hashing,
+    /// extracting the metadata and parsing the datetimes
+    Extracting,
+    /// copying the files and recording them
+    Placing,
+    Done,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Phase::Scanning => "scanning",
+            Phase::Hashing => "hashing",
+            Phase::Extracting => "extracting",
+            Phase::Placing => "placing",
+            Phase::Done => "done",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// a snapshot of the progress of a place job, see `PlaceOptions::on_progress`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Progress {
+    pub phase: Phase,
+    /// the files and bytes found by scanning, zero while scanning
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub hashed: usize,
+    pub extracted: usize,
+    pub placed: usize,
+    pub placed_bytes: u64,
+    pub failed: usize,
+    /// the time since the scanning is done
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn files_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            s if s > 0.0 => self.placed as f64 / s,
+            _ => 0.0,
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            s if s > 0.0 => self.placed_bytes as f64 / s,
+            _ => 0.0,
+        }
+    }
+
+    /// the estimated remaining time by the bytes placed, or by the files if the files are empty
+    pub fn eta(&self) -> Option<Duration> {
+        if self.phase == Phase::Done {
+            return Some(Duration::ZERO);
+        }
+        let seconds = if self.total_bytes > 0 && self.placed_bytes > 0 {
+            self.total_bytes.saturating_sub(self.placed_bytes) as f64 / self.bytes_per_second()
+        } else if self.placed > 0 {
+            let done = self.placed + self.failed;
+            self.total_files.saturating_sub(done) as f64 / self.files_per_second()
+        } else {
+            return None;
+        };
+        seconds
+            .is_finite()
+            .then(|| Duration::from_secs_f64(seconds))
+    }
+}
+
+/// the callback to receive the progress.
+#[derive(Clone)]
+pub(crate) struct ProgressFn(pub(crate) Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl std::fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressFn")
+    }
+}
+
+// 各个步骤的计数，在解析和归档的任务之间共享
+#[derive(Debug, Default)]
+pub(crate) struct Tracker {
+    callback: Option<ProgressFn>,
+    scanned: OnceLock<Instant>,
+    done: AtomicBool,
+    total_files: AtomicUsize,
+    total_bytes: AtomicU64,
+    hashed: AtomicUsize,
+    extracted: AtomicUsize,
+    placed: AtomicUsize,
+    placed_bytes: AtomicU64,
+    failed: AtomicUsize,
+    // 在哈希和解析时失败的文件，不会再完成后续的步骤
+    failed_hashing: AtomicUsize,
+    failed_extracting: AtomicUsize,
+    last: Mutex<Option<Instant>>,
+}
+
+impl Tracker {
+    pub(crate) fn new(callback: Option<ProgressFn>) -> Self {
+        Tracker {
+            callback,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn scanned(&self, files: usize, bytes: u64) {
+        self.total_files.store(files, Ordering::SeqCst);
+        self.total_bytes.store(bytes, Ordering::SeqCst);
+        let _ = self.scanned.set(Instant::now());
+        self.notify(true);
+    }
+
+    pub(crate) fn hashed(&self) {
+        self.hashed.fetch_add(1, Ordering::SeqCst);
+        self.notify(false);
+    }
+
+    pub(crate) fn extracted(&self) {
+        self.extracted.fetch_add(1, Ordering::SeqCst);
+        self.notify(false);
+    }
+
+    pub(crate) fn placed(&self, bytes: u64) {
+        self.placed.fetch_add(1, Ordering::SeqCst);
+        self.placed_bytes.fetch_add(bytes, Ordering::SeqCst);
+        self.notify(false);
+    }
+
+    // 失败的文件不会再进入后续的步骤，需要计入所处步骤及之后的完成数
+    pub(crate) fn failed(&self, phase: Phase) {
+        match phase {
+            Phase::Hashing => self.failed_hashing.fetch_add(1, Ordering::SeqCst),
+            Phase::Extracting => self.failed_extracting.fetch_add(1, Ordering::SeqCst),
+            _ => 0,
+        };
+        self.failed.fetch_add(1, Ordering::SeqCst);
+        self.notify(false);
+    }
+
+    pub(crate) fn finish(&self) {
+        self.done.store(true, Ordering::SeqCst);
+        self.notify(true);
+    }
+
+    pub(crate) fn snapshot(&self) -> Progress {
+        let total_files = self.total_files.load(Ordering::SeqCst);
+        let (hashed, extracted, placed, failed) = (
+            self.hashed.load(Ordering::SeqCst),
+            self.extracted.load(Ordering::SeqCst),
+            self.placed.load(Ordering::SeqCst),
+            self.failed.load(Ordering::SeqCst),
+        );
+        let hashing = self.failed_hashing.load(Ordering::SeqCst);
+        let extracting = hashing + self.failed_extracting.load(Ordering::SeqCst);
+        let phase = match self.scanned.get() {
+            _ if self.done.load(Ordering::SeqCst) => Phase::Done,
+            None => Phase::Scanning,
+            Some(_) if hashed + hashing < total_files => Phase::Hashing,
+            Some(_) if extracted + extracting < total_files => Phase::Extracting,
+            Some(_) => Phase::Placing,
+        };
+        Progress {
+            phase,
+            total_files,
+            total_bytes: self.total_bytes.load(Ordering::SeqCst),
+            hashed,
+            extracted,
+            placed,
+            placed_bytes: self.placed_bytes.load(Ordering::SeqCst),
+            failed,
+            elapsed: self.scanned.get().map_or(Duration::ZERO, |s| s.elapsed()),
+        }
+    }
+
+    // 非强制时按间隔节流，回调在锁内执行，保证输出不交错
+    pub(crate) fn notify(&self, force: bool) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        let mut last = self.last.lock().unwrap();
+        if !force && last.is_some_and(|l| l.elapsed() < INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        (callback.0)(&self.snapshot());
+    }
+}
+
+// 1536 -> 1.5 KiB
+pub(crate) fn human_bytes(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", value as u64, units[0]),
+        _ => format!("{:.1} {}", value, units[unit]),
+    }
+}
+
+// 3725s -> 1:02:05
+fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// the one line display of the progress, e.g. `placing 12/100 files, 1.0 MiB/8.0 MiB, 2.0 MiB/s, 3.1 files/s, ETA 0:00:04`.
+pub fn format_progress(p: &Progress) -> String {
+    if p.phase == Phase::Scanning {
+        return "scanning the inputs...".to_string();
+    }
+    let eta = p.eta().map_or("--".to_string(), human_duration);
+    format!(
+        "{} {}/{} files, {}/{}, {}/s, {:.1} files/s, ETA {}",
+        p.phase,
+        p.placed,
+        p.total_files,
+        human_bytes(p.placed_bytes as f64),
+        human_bytes(p.total_bytes as f64),
+        human_bytes(p.bytes_per_second()),
+        p.files_per_second(),
+        eta
+    )
+}
+
+/// the progress line redrawn at the bottom of the terminal, none if the stderr is not a terminal.
+pub(crate) fn terminal() -> Option<ProgressFn> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    Some(ProgressFn(Arc::new(|p: &Progress| {
+        let mut stderr = std::io::stderr().lock();
+        // 日志输出前会清除进度行，见 `utils::log::PROGRESS_LINE`
+        let _ = write!(stderr, "\r\x1b[2K{}", format_progress(p));
+        if p.phase == Phase::Done {
+            let _ = writeln!(stderr);
+            utils::log::PROGRESS_LINE.store(false, Ordering::SeqCst);
+        } else {
+            utils::log::PROGRESS_LINE.store(true, Ordering::SeqCst);
+        }
+        let _ = stderr.flush();
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let callback = {
+            let seen = seen.clone();
+            ProgressFn(Arc::new(move |p: &Progress| {
+                seen.lock().unwrap().push(p.phase)
+            }))
+        };
+        let tracker = Tracker::new(Some(callback));
+        assert_eq!(tracker.snapshot().phase, Phase::Scanning);
+        tracker.scanned(2, 2048);
+        tracker.hashed();
+        assert_eq!(tracker.snapshot().phase, Phase::Hashing);
+        tracker.hashed();
+        tracker.extracted();
+        tracker.extracted();
+        tracker.placed(1024);
+        let p = tracker.snapshot();
+        assert_eq!(p.phase, Phase::Placing);
+        assert_eq!((p.placed, p.placed_bytes), (1, 1024));
+        assert!(format_progress(&p).starts_with("placing 1/2 files, 1.0 KiB/2.0 KiB"));
+        tracker.placed(1024);
+        tracker.finish();
+        assert_eq!(tracker.snapshot().eta(), Some(Duration::ZERO));
+        // 节流之后只有强制的通知
+        assert_eq!(*seen.lock().unwrap(), vec![Phase::Hashing, Phase::Done]);
+    }
+
+    #[test]
+    fn test_tracker_failed() {
+        let tracker = Tracker::new(None);
+        tracker.scanned(3, 0);
+        // a file failed after hashing is still extracting
+        tracker.hashed();
+        tracker.hashed();
+        tracker.failed(Phase::Extracting);
+        assert_eq!(tracker.snapshot().phase, Phase::Hashing);
+        tracker.failed(Phase::Hashing);
+        assert_eq!(tracker.snapshot().phase, Phase::Extracting);
+        tracker.extracted();
+        let p = tracker.snapshot();
+        assert_eq!((p.phase, p.failed), (Phase::Placing, 2));
+        tracker.failed(Phase::Placing);
+        assert_eq!(tracker.snapshot().phase, Phase::Placing);
+        tracker.finish();
+        assert_eq!(tracker.snapshot().phase, Phase::Done);
+    }
+
+    #[test]
+    fn test_human() {
+        assert_eq!(human_bytes(512.0), "512 B");
+        assert_eq!(human_bytes(1536.0), "1.5 KiB");
+        assert_eq!(human_bytes(3.0 * 1024.0 * 1024.0 * 1024.0), "3.0 GiB");
+        assert_eq!(human_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+}
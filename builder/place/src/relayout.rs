@@ -0,0 +1,309 @@
+use anyhow::Result;
+use chrono::{Datelike, Local};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, finish_run, insert_action, insert_run, query_finfo_all, update_parts,
+};
+use super::target::{OUTPUT_GEN, Target, UNKNOWN_DATE_DIR, remove_empty_dirs};
+
+// 归档文件的移动计划
+#[derive(Debug)]
+struct Move {
+    from: PathBuf,
+    to: PathBuf,
+    parts: Vec<String>,
+}
+
+/// the result of `place::relayout`, the paths are relative to the output.
+#[derive(Debug, Clone, Default)]
+pub struct RelayoutReport {
+    /// the number of records
+    pub total: usize,
+    /// the records whose archived file is not found
+    pub missing: usize,
+    /// the moves planned in dry run, otherwise the files moved
+    pub moves: Vec<(PathBuf, PathBuf)>,
+    /// the run of the moves, none in dry run
+    pub run: Option<i64>,
+}
+
+impl RelayoutReport {
+    /// the lines printed by the `relayout` command.
+    pub fn lines(&self) -> Vec<String> {
+        let verb = if self.run.is_some() { "moved" } else { "move" };
+        let mut lines: Vec<String> = self
+            .moves
+            .iter()
+            .map(|(from, to)| format!("{} {} -> {}", verb, from.display(), to.display()))
+            .collect();
+        lines.push(match self.run {
+            Some(run) => format!(
+                "{} of {} files moved, run {}",
+                self.moves.len(),
+                self.total,
+                run
+            ),
+            None => format!(
+                "{} of {} files will be moved, {} missing",
+                self.moves.len(),
+                self.total,
+                self.missing
+            ),
+        });
+        lines
+    }
+}
+
+// 按当前的布局规则生成 parts，文件名优先使用源文件名，与 `Target::set_output_parts` 一致
+pub(crate) fn layout_target(file: &Path, finfo: &FileInfo<String>) -> Result<Target> {
+    let mut target =
+        Target::with_hash(file.to_path_buf(), finfo.hash.to_string(), &finfo.algorithm)?;
+    if let Some(stem) = finfo
+        .source
+        .as_deref()
+        .and_then(|s| Path::new(s).file_stem())
+    {
+        target.name = stem.to_string_lossy().to_lowercase();
+    }
+    // 归档文件的扩展名已经是解析出的文件类型
+    target.ftype = Some(target.extension.clone());
+    target.set_earliest(Some(finfo.earliest as u64))?;
+    Ok(target)
+}
+
+// 在 `unknown-date/` 中的文件保留前缀，直到通过 `set-date` 确认日期
+fn layout_prefix(finfo: &FileInfo<String>) -> Option<&'static str> {
+    (finfo.parts.len() > 3 && finfo.parts[0] == UNKNOWN_DATE_DIR).then_some(UNKNOWN_DATE_DIR)
+}
+
+pub(crate) fn layout_parts(
+    target: &Target,
+    prefix: Option<&str>,
+    i: usize,
+    rename_with_ymd: bool,
+) -> Result<Vec<String>> {
+    let earliest = target.get_earliest()?;
+    let name = rename_with_ymd.then(|| {
+        format!(
+            "{}-{:02}-{:02}",
+            earliest.year(),
+            earliest.month(),
+            earliest.day()
+        )
+    });
+    let mut parts: Vec<String> = prefix.into_iter().map(str::to_string).collect();
+    parts.extend([
+        earliest.year().to_string(),
+        format!("{:02}", earliest.month()),
+        target.get_name(i, name.as_deref()),
+    ]);
+    Ok(parts)
+}
+
+// 计算所有记录的新路径，新路径只能是当前路径或者不存在的路径，不会覆盖任何文件
+fn plan(
+    output: &Path,
+    records: &[FileInfo<String>],
+    rename_with_ymd: bool,
+) -> Result<(Vec<Option<Move>>, usize)> {
+    let mut claimed = HashSet::new();
+    let mut targets = Vec::new();
+    let mut missing = 0;
+    for finfo in records.iter() {
+        let file = OUTPUT_GEN(output, &finfo.parts);
+        if !file.is_file() {
+            warn!(file=?file, "⚠️ the archived file not found, skip it");
+            missing += 1;
+            targets.push(None);
+            continue;
+        }
+        let target = layout_target(&file, finfo)?;
+        // 路径不需要变化的先占用，避免被其他文件占用后重命名
+        if layout_parts(&target, layout_prefix(finfo), 0, rename_with_ymd)? == finfo.parts.as_ref()
+        {
+            claimed.insert(file.clone());
+        }
+        targets.push(Some((file, target)));
+    }
+
+    let mut moves = Vec::new();
+    for (finfo, target) in records.iter().zip(targets) {
+        let Some((file, target)) = target else {
+            moves.push(None);
+            continue;
+        };
+        if claimed.contains(&file) {
+            moves.push(None);
+            continue;
+        }
+        let mut planned = None;
+        for i in 0..1000 {
+            let parts = layout_parts(&target, layout_prefix(finfo), i, rename_with_ymd)?;
+            if parts == finfo.parts.as_ref() {
+                break;
+            }
+            let to = OUTPUT_GEN(output, &parts);
+            if !claimed.contains(&to) && !to.exists() {
+                planned = Some(Move {
+                    from: file.clone(),
+                    to,
+                    parts,
+                });
+                break;
+            }
+        }
+        claimed.insert(planned.as_ref().map_or(file, |m| m.to.clone()));
+        moves.push(planned);
+    }
+    Ok((moves, missing))
+}
+
+// 按当前的布局规则重新整理归档目录，移动文件并更新 parts，dry run 时只输出移动计划
+pub fn do_relayout(
+    ctx: &Context,
+    output: PathBuf,
+    rename_with_ymd: bool,
+    dry_run: bool,
+) -> Result<RelayoutReport> {
+    let conn = ctx.conn()?.lock().unwrap();
+    let mut records = query_finfo_all(&conn)?;
+    records.sort_by(|a, b| a.parts.cmp(&b.parts));
+    let (moves, missing) = plan(&output, &records, rename_with_ymd)?;
+    let total = moves.iter().flatten().count();
+    info!(
+        records = records.len(),
+        moves = total,
+        missing,
+        dry_run,
+        "relayout plan done"
+    );
+
+    let relative = |f: &Path| f.strip_prefix(&output).unwrap_or(f).to_path_buf();
+    let mut report = RelayoutReport {
+        total: records.len(),
+        missing,
+        ..Default::default()
+    };
+    if dry_run {
+        report.moves = moves
+            .iter()
+            .flatten()
+            .map(|m| (relative(&m.from), relative(&m.to)))
+            .collect();
+        return Ok(report);
+    }
+
+    // 移动作为一次运行记录，可以通过 `undo` 撤销
+    let started = Local::now().timestamp();
+    let run = insert_run(&conn, started)?;
+    for (finfo, m) in records.iter().zip(moves.iter()) {
+        let Some(m) = m else {
+            continue;
+        };
+        if m.to.exists() {
+            warn!(file=?m.to, "⚠️ the destination exists, skip it");
+            continue;
+        }
+        if let Some(dir) = m.to.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::rename(&m.from, &m.to)?;
+        let tx = conn.unchecked_transaction()?;
+        update_parts(&tx, &finfo.hash, &m.parts)?;
+        insert_action(
+            &tx,
+            &ActionInfo {
+                run_id: run.id,
+                hash: finfo.hash.to_string(),
+                source: Some(m.from.to_string_lossy().to_string()),
+                destination: Some(m.to.to_string_lossy().to_string()),
+                action: "move".to_string(),
+                timestamp: Local::now().timestamp(),
+                previous: Some(serde_json::to_string(finfo)?),
+                ..Default::default()
+            },
+        )?;
+        if let Err(e) = tx.commit() {
+            // 数据库更新失败时移回原路径
+            std::fs::rename(&m.to, &m.from)?;
+            return Err(e.into());
+        }
+        remove_empty_dirs(&m.from, &output);
+        report.moves.push((relative(&m.from), relative(&m.to)));
+    }
+
+    finish_run(
+        &conn,
+        run,
+        &output,
+        json!({ "relayout": output }),
+        Some(json!({ "rename_with_ymd": rename_with_ymd })),
+        json!({ "total": records.len(), "moved": report.moves.len(), "missing": missing }),
+    )?;
+    report.run = Some(run.id);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::borrow::Cow;
+
+    fn finfo(parts: &[&str], hash: &str, earliest: i64) -> FileInfo<'static, String> {
+        FileInfo {
+            parts: Cow::Owned(parts.iter().map(|p| p.to_string()).collect()),
+            hash: Cow::Owned(hash.to_string()),
+            earliest,
+            source: Some(Cow::Owned(format!("/from/{}.JPG", hash))),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let output = std::env::temp_dir().join("mmfplace_test_relayout");
+        let earliest = Local
+            .with_ymd_and_hms(2003, 1, 2, 12, 0, 0)
+            .unwrap()
+            .timestamp();
+        let records = vec![
+            finfo(&["2003", "01", "a.jpg"], "a", earliest),
+            finfo(&["2002", "11", "b.jpg"], "b", earliest),
+            finfo(&["2002", "11", "c.jpg"], "c", earliest),
+            finfo(&["2002", "11", "missing.jpg"], "d", earliest),
+            finfo(&[UNKNOWN_DATE_DIR, "2002", "11", "e.jpg"], "e", earliest),
+        ];
+        for r in records.iter().filter(|r| r.hash != "d") {
+            let file = OUTPUT_GEN(&output, &r.parts);
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, r.hash.as_bytes()).unwrap();
+        }
+        // a file not in the database takes the path
+        std::fs::write(output.join("2003/01/c.jpg"), b"x").unwrap();
+
+        let (moves, missing) = plan(&output, &records, false).unwrap();
+        assert_eq!(missing, 1);
+        assert!(moves[0].is_none() && moves[3].is_none());
+        let to = |i: usize| moves[i].as_ref().unwrap().parts.join("/");
+        assert_eq!(to(1), "2003/01/b.jpg");
+        assert_eq!(to(2), "2003/01/c_01.jpg");
+        assert_eq!(to(4), "unknown-date/2003/01/e.jpg");
+
+        let (moves, _) = plan(&output, &records, true).unwrap();
+        assert_eq!(
+            moves[0].as_ref().unwrap().parts.join("/"),
+            "2003/01/2003-01-02.jpg"
+        );
+        assert_eq!(
+            moves[1].as_ref().unwrap().parts.join("/"),
+            "2003/01/2003-01-02_01.jpg"
+        );
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{SourceIndex, SourceInfo, query_finfo, query_index, query_sources, upsert_index};
+use super::input::{Inputs, WalkErrors, WalkOptions};
+use super::overrides::resolve_hash;
+use super::target::OUTPUT_GEN;
+use utils::crypto::get_file_hash;
+
+// 优先使用源文件索引中的 hash，文件变化或不存在时重新计算并更新索引
+fn indexed_hash(ctx: &Context, path: &Path) -> Result<String> {
+    let mut index = SourceIndex::stat(path)?;
+    index.algorithm = ctx.hash_algorithm().to_string();
+    {
+        let conn = ctx.conn()?.lock().unwrap();
+        if let Some(found) = query_index(&conn, &index.path)?
+            && found.unchanged(&index)
+        {
+            return Ok(found.hash);
+        }
+    }
+    index.hash = get_file_hash(ctx.hash_algorithm(), path)?;
+    upsert_index(&ctx.conn()?.lock().unwrap(), &index)?;
+    Ok(index.hash)
+}
+
+// hash 在数据库中存在，且指定了归档目录时归档文件也存在
+fn is_archived(ctx: &Context, hash: &str, output: Option<&Path>) -> Result<bool> {
+    let conn = ctx.conn()?.lock().unwrap();
+    Ok(match query_finfo(&conn, hash)? {
+        Some(finfo) => output.is_none_or(|o| OUTPUT_GEN(o, &finfo.parts).is_file()),
+        None => false,
+    })
+}
+
+/// the files in an input root which are not archived yet.
+#[derive(Debug, Clone, Default)]
+pub struct Unarchived {
+    pub root: PathBuf,
+    /// the number of files walked
+    pub total: usize,
+    pub files: Vec<PathBuf>,
+    /// the entries could not be walked, the root is not fully checked if not 0
+    pub walk_errors: usize,
+}
+
+/// the result of `place::report`, see `check` for the roots not fully walked.
+#[derive(Debug, Clone, Default)]
+pub struct UnarchivedReport {
+    pub roots: Vec<Unarchived>,
+}
+
+impl UnarchivedReport {
+    /// the lines printed by the `report` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for r in self.roots.iter() {
+            if r.walk_errors > 0 {
+                lines.push(format!(
+                    "{}: incomplete, {} entries could not be walked, {} of {} walked files are not archived",
+                    r.root.display(),
+                    r.walk_errors,
+                    r.files.len(),
+                    r.total
+                ));
+            } else if r.files.is_empty() {
+                lines.push(format!(
+                    "{}: all {} files are archived",
+                    r.root.display(),
+                    r.total
+                ));
+                continue;
+            } else {
+                lines.push(format!(
+                    "{}: {} of {} files are not archived",
+                    r.root.display(),
+                    r.files.len(),
+                    r.total
+                ));
+            }
+            for file in r.files.iter() {
+                lines.push(format!("  {}", file.display()));
+            }
+        }
+        lines
+    }
+
+    /// the error if any root could not be fully walked, which is not safe to remove.
+    pub fn check(&self) -> Result<()> {
+        let incomplete = self.roots.iter().filter(|r| r.walk_errors > 0).count();
+        if incomplete > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} inputs could not be fully walked, they are not safe to remove",
+                incomplete,
+                self.roots.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// the result of `place::origin`.
+#[derive(Debug, Clone, Default)]
+pub struct OriginReport {
+    pub hash: String,
+    /// the relative path in the archive, none if not archived
+    pub archived: Option<String>,
+    /// all the source paths seen for the hash
+    pub sources: Vec<SourceInfo>,
+}
+
+impl OriginReport {
+    /// the lines printed by the `origin` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(archived) = &self.archived {
+            lines.push(format!("{} {}", self.hash, archived));
+        }
+        for s in self.sources.iter() {
+            lines.push(format!("  {} (run {})", s.path, s.run_id));
+        }
+        lines
+    }
+}
+
+// 返回输入目录中未归档的文件，无法读取的文件也视为未归档
+async fn unarchived(ctx: &Context, root: &Path, output: Option<&Path>) -> Result<Unarchived> {
+    let inputs = Inputs::Roots {
+        roots: vec![root.to_path_buf()],
+        walk: WalkOptions::default(),
+    };
+    let errors = WalkErrors::default();
+    let semaphore = Arc::new(Semaphore::new(ctx.batch()));
+    let mut handles = Vec::new();
+    for file in inputs.files(Some(errors.clone())) {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let output = output.map(Path::to_path_buf);
+        let ctx = ctx.clone();
+        handles.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            match indexed_hash(&ctx, &file).and_then(|h| is_archived(&ctx, &h, output.as_deref())) {
+                Ok(true) => None,
+                Ok(false) => Some(file),
+                Err(e) => {
+                    warn!(file=?file, error=%e, "⚠️ check file failed");
+                    Some(file)
+                }
+            }
+        }));
+    }
+    let total = handles.len();
+    let mut files = Vec::new();
+    for handle in handles {
+        files.extend(handle.await?);
+    }
+    let walk_errors = errors.lock().unwrap().len();
+    if walk_errors > 0 {
+        warn!(root=?root, walk_errors, "⚠️ some entries could not be walked, the root is not fully checked");
+    }
+    files.sort();
+    Ok(Unarchived {
+        root: root.to_path_buf(),
+        total,
+        files,
+        walk_errors,
+    })
+}
+
+// 按输入目录列出未归档的文件，全部归档的目录可以安全清理，有遍历错误的目录不能确定
+pub async fn do_report(
+    ctx: &Context,
+    roots: Vec<PathBuf>,
+    output: Option<PathBuf>,
+) -> Result<UnarchivedReport> {
+    let mut report = UnarchivedReport::default();
+    for root in roots.iter() {
+        let r = unarchived(ctx, root, output.as_deref()).await?;
+        info!(root=?root, total = r.total, unarchived = r.files.len(), walk_errors = r.walk_errors, "check archived done");
+        report.roots.push(r);
+    }
+    Ok(report)
+}
+
+// 列出同一个 hash 的所有源文件路径，参数可以是文件或者 hash，与 `set-date` 一致
+pub fn do_origin(ctx: &Context, target: &str) -> Result<OriginReport> {
+    let hash = resolve_hash(ctx, target)?;
+    let conn = ctx.conn()?.lock().unwrap();
+    let sources = query_sources(&conn, &hash)?;
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("no source found for {}", hash));
+    }
+    let archived = query_finfo(&conn, &hash)?.map(|finfo| finfo.parts.join("/"));
+    Ok(OriginReport {
+        hash,
+        archived,
+        sources,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unarchived() {
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let root = std::env::temp_dir().join("mmfplace_test_unarchived");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.jpg"), b"a").unwrap();
+        let checked = unarchived(&ctx, &root, None).await.unwrap();
+        assert_eq!((checked.total, checked.walk_errors), (1, 0));
+        assert_eq!(checked.files, vec![root.join("a.jpg")]);
+
+        // the root could not be walked, nothing is reported as archived
+        let missing = root.join("missing");
+        let checked = unarchived(&ctx, &missing, None).await.unwrap();
+        assert_eq!((checked.total, checked.walk_errors), (0, 1));
+        let report = do_report(&ctx, vec![root.clone(), missing], None)
+            .await
+            .unwrap();
+        assert!(report.lines()[0].ends_with("1 of 1 files are not archived"));
+        assert!(report.lines()[2].contains("incomplete, 1 entries could not be walked"));
+        assert!(report.check().is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_origin() {
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let e = do_origin(&ctx, "./typo.jpg").unwrap_err();
+        assert!(e.to_string().contains("not found"));
+        let hash = "a18932e314dbb4c81c6fd0e282d81d16";
+        let e = do_origin(&ctx, hash).unwrap_err();
+        assert_eq!(e.to_string(), format!("no source found for {}", hash));
+    }
+}
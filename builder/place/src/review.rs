@@ -0,0 +1,62 @@
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+
+use super::context::Context;
+use super::db::query_finfo_undated;
+
+/// an archived file without any datetime in metadata.
+#[derive(Debug, Clone, Default)]
+pub struct UndatedFile {
+    pub hash: String,
+    /// the relative path in the archive
+    pub path: String,
+    /// the date used to place the file, from the file times
+    pub earliest: i64,
+    pub source: Option<String>,
+}
+
+/// the result of `place::review_list`.
+#[derive(Debug, Clone, Default)]
+pub struct ReviewReport {
+    pub files: Vec<UndatedFile>,
+}
+
+impl ReviewReport {
+    /// the lines printed by the `review list` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for f in self.files.iter() {
+            let earliest = Local
+                .timestamp_opt(f.earliest, 0)
+                .single()
+                .map_or("-".to_string(), |e| e.format("%Y-%m-%d").to_string());
+            lines.push(format!(
+                "{} {} {} {}",
+                f.hash,
+                f.path,
+                earliest,
+                f.source.as_deref().unwrap_or("-")
+            ));
+        }
+        lines.push(format!(
+            "{} files without datetime in metadata, assign the date by `set-date <file|hash> <date>`",
+            self.files.len()
+        ));
+        lines
+    }
+}
+
+// 列出没有从元数据中解析出时间的归档文件
+pub fn do_review_list(ctx: &Context) -> Result<ReviewReport> {
+    let conn = ctx.conn()?.lock().unwrap();
+    let files = query_finfo_undated(&conn)?
+        .into_iter()
+        .map(|finfo| UndatedFile {
+            hash: finfo.hash.to_string(),
+            path: finfo.parts.join("/"),
+            earliest: finfo.earliest,
+            source: finfo.source.map(|s| s.to_string()),
+        })
+        .collect();
+    Ok(ReviewReport { files })
+}
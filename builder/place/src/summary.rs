@@ -0,0 +1,195 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::db::DATA_DIR;
+use super::process::{Placed, RunCounts};
+use super::progress::human_bytes;
+
+/// a file which is not placed because of an error.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// the summary of a place run, printed at the end and saved as `.mmfplace/summary-<run>.json` in the output.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    /// the run id, 0 in test mode
+    pub run: i64,
+    pub test: bool,
+    pub output: PathBuf,
+    pub started: i64,
+    pub ended: i64,
+    /// the elapsed seconds
+    pub elapsed: f64,
+    pub counts: RunCounts,
+    /// the files without any datetime in the metadata, placed by the file times
+    pub filetime_only: usize,
+    /// the number of files by the year of the earliest datetime
+    pub years: BTreeMap<i32, usize>,
+    /// the bytes copied into the output
+    pub bytes_copied: u64,
+    pub failed: Vec<FailedFile>,
+    /// the entries which could not be walked
+    pub walk_errors: Vec<String>,
+}
+
+impl RunSummary {
+    // 单个文件的处理结果，copied 为实际拷贝的字节数
+    pub(crate) fn add(
+        &mut self,
+        placed: Placed,
+        year: Option<i32>,
+        filetime_only: bool,
+        copied: u64,
+    ) {
+        self.counts.add(placed);
+        if let Some(year) = year {
+            *self.years.entry(year).or_default() += 1;
+        }
+        if filetime_only {
+            self.filetime_only += 1;
+        }
+        self.bytes_copied += copied;
+    }
+
+    pub fn path(output: &Path, run: i64) -> PathBuf {
+        output.join(DATA_DIR).join(format!("summary-{}.json", run))
+    }
+
+    pub(crate) fn write(&self) -> Result<PathBuf> {
+        let path = Self::path(&self.output, self.run);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// the error if any file failed, the other files are still placed and recorded.
+    pub fn check(&self) -> Result<()> {
+        match self.counts.failed {
+            0 => Ok(()),
+            n => Err(anyhow::anyhow!(
+                "{} of {} files failed to place, see the summary",
+                n,
+                self.counts.total
+            )),
+        }
+    }
+
+    /// the lines printed to the console
+    pub fn lines(&self) -> Vec<String> {
+        let c = &self.counts;
+        let mut lines = vec![
+            match self.test {
+                true => "test run summary".to_string(),
+                false => format!("run {} summary", self.run),
+            },
+            format!("  total:               {}", c.total),
+        ];
+        // test mode 没有查询数据库，也没有拷贝文件，只有计划归档的文件数
+        if self.test {
+            lines.push(format!("  would place:         {}", c.planned));
+        } else {
+            lines.extend([
+                format!("  new:                 {}", c.created),
+                format!("  skipped-known:       {}", c.skipped),
+                format!("  replaced-by-earlier: {}", c.replaced),
+                format!("  restored:            {}", c.restored),
+                format!("  conflicted:          {}", c.conflicts),
+            ]);
+        }
+        lines.extend([
+            format!("  failed:              {}", c.failed),
+            format!("  filetime-only:       {}", self.filetime_only),
+        ]);
+        if !self.test {
+            lines.push(format!(
+                "  bytes copied:        {}",
+                human_bytes(self.bytes_copied as f64)
+            ));
+        }
+        lines.push(format!("  elapsed:             {:.1}s", self.elapsed));
+        if !self.years.is_empty() {
+            lines.push("  by year:".to_string());
+            lines.extend(self.years.iter().map(|(y, n)| format!("    {}: {}", y, n)));
+        }
+        if !self.failed.is_empty() {
+            lines.push("  failed files:".to_string());
+            lines.extend(
+                self.failed
+                    .iter()
+                    .map(|f| format!("    {}: {}", f.path.display(), f.error)),
+            );
+        }
+        if !self.walk_errors.is_empty() {
+            lines.push(format!("  walk errors: {}", self.walk_errors.len()));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut summary = RunSummary {
+            run: 3,
+            output: std::env::temp_dir().join("mmfplace-test-summary"),
+            ..Default::default()
+        };
+        summary.add(Placed::Created, Some(2002), false, 100);
+        summary.add(Placed::Skipped, Some(2002), true, 0);
+        summary.add(Placed::Replaced, Some(2010), false, 10);
+        // the archived file is kept by the conflict policy, nothing is copied
+        summary.add(Placed::Created, Some(2010), false, 0);
+        assert_eq!(summary.counts.created, 2);
+        assert_eq!(summary.filetime_only, 1);
+        assert_eq!(summary.bytes_copied, 110);
+        assert_eq!(summary.years, BTreeMap::from([(2002, 2), (2010, 2)]));
+        let lines = summary.lines();
+        assert!(lines.contains(&"  skipped-known:       1".to_string()));
+        assert!(lines.contains(&"    2010: 2".to_string()));
+        assert!(summary.check().is_ok());
+
+        let path = summary.write().unwrap();
+        assert!(path.ends_with(".mmfplace/summary-3.json"));
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["counts"]["created"], 2);
+        assert_eq!(json["years"]["2002"], 2);
+        std::fs::remove_dir_all(&summary.output).unwrap();
+
+        summary.counts.total = 4;
+        summary.counts.failed = 1;
+        assert_eq!(
+            summary.check().unwrap_err().to_string(),
+            "1 of 4 files failed to place, see the summary"
+        );
+    }
+
+    #[test]
+    fn test_summary_test_mode() {
+        let mut summary = RunSummary {
+            test: true,
+            ..Default::default()
+        };
+        summary.add(Placed::Planned, Some(2002), false, 0);
+        summary.add(Placed::Planned, Some(2003), false, 0);
+        summary.counts.total = 2;
+        let lines = summary.lines();
+        assert_eq!(lines[0], "test run summary");
+        assert!(lines.contains(&"  would place:         2".to_string()));
+        assert!(
+            !lines
+                .iter()
+                .any(|l| l.contains("skipped-known") || l.contains("bytes copied"))
+        );
+    }
+}
@@ -2,11 +2,14 @@ use anyhow::Result;
 use chrono::prelude::*;
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{debug, error, info, warn};
 
-use utils::crypto::get_file_md5;
+use super::db::DATA_DIR;
+use super::media::MediaInfo;
+use utils::crypto::get_file_hash;
 
 // output generation
 pub static OUTPUT_GEN: Lazy<fn(&Path, &[String]) -> PathBuf> = Lazy::new(|| {
@@ -18,17 +21,192 @@ pub static OUTPUT_GEN: Lazy<fn(&Path, &[String]) -> PathBuf> = Lazy::new(|| {
     }
 });
 
+/// the trash directory in the output, the overwritten or deleted archived files of a run are moved into `<output>/.mmfplace-trash/<run>/`.
+pub const TRASH_DIR: &str = ".mmfplace-trash";
+
+/// the directory in the output for the files without any datetime in metadata, see `place --unknown-date`.
+pub const UNKNOWN_DATE_DIR: &str = "unknown-date";
+
+/// what to do when an archived file was modified and is about to be replaced, see config `conflict`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// keep the modified file and do not copy
+    Skip,
+    /// rename the modified file beside it
+    Rename,
+    /// replace the modified file
+    Overwrite,
+    /// move the modified file to the trash directory
+    #[default]
+    Trash,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "rename" => Ok(Self::Rename),
+            "overwrite" => Ok(Self::Overwrite),
+            "trash" => Ok(Self::Trash),
+            _ => Err(anyhow::anyhow!("unsupported conflict policy: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Skip => "skip",
+            Self::Rename => "rename",
+            Self::Overwrite => "overwrite",
+            Self::Trash => "trash",
+        };
+        f.write_str(s)
+    }
+}
+
+// `<stem>.<suffix>.<ext>`
+fn with_suffix(file: &Path, suffix: &str) -> PathBuf {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    file.with_file_name(match file.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, suffix),
+    })
+}
+
+// 回收站、数据库等程序生成的文件不属于归档
+pub fn is_internal(file: &Path, output: &Path) -> bool {
+    file.strip_prefix(output)
+        .ok()
+        .and_then(|r| r.components().next())
+        .is_some_and(|c| c.as_os_str() == TRASH_DIR || c.as_os_str() == DATA_DIR)
+}
+
+// 删除文件后清理空的父目录，直到 root 为止
+pub fn remove_empty_dirs(file: &Path, root: &Path) {
+    for dir in file.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+// 重名时在扩展名前追加序号
+fn unused_path(base: PathBuf) -> PathBuf {
+    let mut path = base.clone();
+    for i in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = with_suffix(&base, &i.to_string());
+    }
+    path
+}
+
+// 移动到回收站，保留相对归档目录的路径
+pub fn move_to_trash(file: &Path, output: &Path, run: i64) -> Result<PathBuf> {
+    let relative = file.strip_prefix(output).unwrap_or(file);
+    let trash = unused_path(output.join(TRASH_DIR).join(run.to_string()).join(relative));
+    if let Some(dir) = trash.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::rename(file, &trash)?;
+    info!(file=?file, trash=?trash, "🗑️ move to trash");
+    Ok(trash)
+}
+
+// 在原目录中重命名为 `<name>.conflict-<run>.<ext>`
+fn rename_conflict(file: &Path, run: i64) -> Result<PathBuf> {
+    let renamed = unused_path(with_suffix(file, &format!("conflict-{}", run)));
+    std::fs::rename(file, &renamed)?;
+    info!(file=?file, renamed=?renamed, "✏️ rename the conflict file");
+    Ok(renamed)
+}
+
+// 按冲突策略处理被修改过的归档文件，返回移动后的路径
+pub fn apply_conflict_policy(
+    file: &Path,
+    output: &Path,
+    run: i64,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>> {
+    let moved = match policy {
+        ConflictPolicy::Skip => None,
+        ConflictPolicy::Rename => Some(rename_conflict(file, run)?),
+        ConflictPolicy::Overwrite => {
+            std::fs::remove_file(file)?;
+            None
+        }
+        ConflictPolicy::Trash => Some(move_to_trash(file, output, run)?),
+    };
+    warn!(file=?file, policy=%policy, moved=?moved, "⚠️ the archived file was modified, resolve the conflict");
+    Ok(moved)
+}
+
+// 设置文件的访问/修改(windows 包括创建)时间
+pub fn set_file_times(file: &Path, time: DateTime<Local>) -> Result<()> {
+    let st: SystemTime = time.into();
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::fs::FileTimesExt;
+        std::fs::File::options().write(true).open(file)?.set_times(
+            std::fs::FileTimes::new()
+                .set_accessed(st)
+                .set_modified(st)
+                .set_created(st),
+        )?
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        std::fs::File::options()
+            .write(true)
+            .open(file)?
+            .set_times(std::fs::FileTimes::new().set_accessed(st).set_modified(st))?
+    }
+    Ok(())
+}
+
+/// what `copy_with_times` did to the output file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Copied {
+    // the output exists with the same hash
+    Skipped,
+    Created,
+    // the output exists with a different hash
+    Overwritten,
+}
+
+/// where the earliest datetime of a file comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provenance {
+    /// parsed from the metadata text
+    Metadata,
+    /// the file attribute times, no earlier datetime is found in the metadata
+    #[default]
+    FileTime,
+    /// recorded in the database by a previous run
+    Recorded,
+    /// assigned by hand, see `set-date`
+    Manual,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct TimeInfo {
     // parsed datetime from metadata
     // all datetime parsed as to utc
     parsedtimes: Vec<DateTime<Utc>>,
+    // the metadata text of each parsed datetime
+    parsedtexts: Vec<String>,
     // datetime from file attributes
     // [accessed, modified, created]
     attrtimes: Vec<Option<SystemTime>>,
     // the earliest datetime, minimum of parsedtimes and attrtimes
     // set it to private `Option` system local time ensure every process should set it.
     earliest: Option<DateTime<Local>>,
+    provenance: Provenance,
 }
 
 // impl Default for TimeInfo {
@@ -49,8 +227,10 @@ pub struct Target {
     parts: Option<Vec<String>>,
     // // parsed datetime from metadata
     // pub datetimes: Vec<DateTime<Utc>>,
-    // hash with md5
+    // hash with the algorithm configured, see `Context::hash_algorithm`
     pub hash: String,
+    // the hash algorithm, used to compare with the output file
+    pub algorithm: String,
     // the original file
     pub extension: String,
     // the file name without extension
@@ -66,14 +246,25 @@ pub struct Target {
     // pub attrtimes: Vec<Option<SystemTime>>,
     // // whether the file has been dealt with before
     pub dealt: bool,
+    // the hash is got from the source index, the file is unchanged since last run
+    pub indexed: bool,
     // the output path
     pub output: PathBuf,
+    // the media info parsed from metadata
+    pub media: MediaInfo,
 }
 
 impl Target {
-    pub fn new(path: PathBuf) -> Result<Self> {
+    pub fn new(path: PathBuf, algorithm: &str) -> Result<Self> {
+        let hash = get_file_hash(algorithm, &path)?;
+        Self::with_hash(path, hash, algorithm)
+    }
+
+    // 使用已知的 hash 创建，如源文件索引中未变化的文件
+    pub fn with_hash(path: PathBuf, hash: String, algorithm: &str) -> Result<Self> {
         let mut target = Target {
-            hash: get_file_md5(&path)?,
+            hash,
+            algorithm: algorithm.to_string(),
             extension: path
                 .extension()
                 .map_or("bin".to_string(), |e| e.to_string_lossy().to_lowercase()),
@@ -105,7 +296,7 @@ impl Target {
         if self.parts.is_none() {
             return Err(anyhow::anyhow!("parts not set"));
         }
-        Ok(&self.parts.as_ref().unwrap())
+        Ok(self.parts.as_ref().unwrap())
     }
 
     // 重名文件添加序号，是/否重命名文件
@@ -115,14 +306,14 @@ impl Target {
             format!(
                 "{}.{}",
                 name,
-                self.ftype.as_ref().map_or(&self.extension, |s| &s)
+                self.ftype.as_ref().map_or(&self.extension, |s| s)
             )
         } else {
             format!(
                 "{}_{:02}.{}",
                 name,
                 i,
-                self.ftype.as_ref().map_or(&self.extension, |s| &s)
+                self.ftype.as_ref().map_or(&self.extension, |s| s)
             )
         }
     }
@@ -153,16 +344,19 @@ impl Target {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn get_attrtime(&self) -> &[Option<SystemTime>] {
         &self.tinfo.attrtimes
     }
 
-    pub fn add_parsedtime(&mut self, dt: DateTime<Utc>) {
+    pub fn add_parsedtime(&mut self, dt: DateTime<Utc>, text: &str) {
         self.tinfo.parsedtimes.push(dt);
+        self.tinfo.parsedtexts.push(text.to_string());
+    }
+
+    pub fn get_parsedtexts(&self) -> &[String] {
+        &self.tinfo.parsedtexts
     }
 
-    #[allow(dead_code)]
     pub fn get_parsedtime(&self) -> &[DateTime<Utc>] {
         &self.tinfo.parsedtimes
     }
@@ -176,9 +370,16 @@ impl Target {
         Ok(())
     }
 
+    // 手动指定的时间优先于元数据和文件时间
+    pub fn set_earliest_manual(&mut self, timestamp: u64) {
+        self.set_earliest_from_timestamp(timestamp);
+        self.tinfo.provenance = Provenance::Manual;
+    }
+
     fn set_earliest_from_timestamp(&mut self, timestamp: u64) {
         let systime: SystemTime = UNIX_EPOCH + Duration::from_secs(timestamp);
         self.tinfo.earliest = Some(systime.into());
+        self.tinfo.provenance = Provenance::Recorded;
     }
 
     fn update_earliest(&mut self) -> Result<()> {
@@ -197,6 +398,7 @@ impl Target {
             // should panic?
             // warn!(file=?self.path, "💡 datetime not found by dateparser");
             self.tinfo.earliest = Some(attr_min);
+            self.tinfo.provenance = Provenance::FileTime;
             warn!(file=?self.path, "💡 time not found by dateparser, use the attrtimes as earliest time");
         } else {
             // self.tinfo.earliest = self
@@ -212,12 +414,25 @@ impl Target {
                 .ok_or(anyhow::anyhow!("min time not found in parsedtimes"))?
                 .with_timezone(&Local);
             self.tinfo.earliest = Some(parsed_min.min(attr_min));
+            self.tinfo.provenance = match parsed_min <= attr_min {
+                true => Provenance::Metadata,
+                false => Provenance::FileTime,
+            };
             debug!(file=?self.path, "use the minimum time of attrtimes and dateparser");
         }
         info!(file=?self.path, earliest = ?self.tinfo.earliest, "🎉 success set earliest datetime");
         Ok(())
     }
 
+    pub fn get_provenance(&self) -> Provenance {
+        self.tinfo.provenance
+    }
+
+    // 元数据中没有解析出时间，只能使用文件时间
+    pub fn is_undated(&self) -> bool {
+        self.tinfo.provenance == Provenance::FileTime && self.tinfo.parsedtimes.is_empty()
+    }
+
     pub fn get_earliest(&self) -> Result<DateTime<Local>> {
         // 强制验证 earliest 是否设置过，否则说明逻辑处理存在缺陷
         self.tinfo
@@ -228,18 +443,30 @@ impl Target {
 
     // 尝试最大 1000 次设置 output 字段，并更新 parts 字段，正常的元数据解析流程只会调用一次
     pub fn set_output_parts(&mut self, dir: &Path, rename_with_ymd: bool) -> Result<()> {
+        self.set_output_parts_in(dir, rename_with_ymd, None)
+    }
+
+    // 指定 prefix 时放到输出目录的子目录中，如 `unknown-date/2002/11/a.jpg`
+    pub fn set_output_parts_in(
+        &mut self,
+        dir: &Path,
+        rename_with_ymd: bool,
+        prefix: Option<&str>,
+    ) -> Result<()> {
         if self.parts.is_some() {
             return Err(anyhow::anyhow!("Unexpected parts already set"));
         }
 
         let earliest = self.get_earliest()?;
         // parse阶段没有标记，说明之前没处理过，生成新路径，并设置新的parts
-        let mut parts: Vec<String> = vec![
+        let mut parts: Vec<String> = prefix.iter().map(|p| p.to_string()).collect();
+        parts.extend([
             earliest.year().to_string(),
             // self.tinfo.earliest.month().to_string(),
             format!("{:02}", earliest.month()),
             "".to_string(),
-        ];
+        ]);
+        let last = parts.len() - 1;
         // 保留文件名格式
         let name: Option<String> = {
             if rename_with_ymd {
@@ -255,7 +482,7 @@ impl Target {
         };
         // 有可能文件重名，循环生成
         for i in 0..1000 {
-            parts[2] = self.get_name(i, name.as_deref());
+            parts[last] = self.get_name(i, name.as_deref());
             let check = OUTPUT_GEN(dir, &parts);
             // 文件不存在，表明该路径可用
             if !check.is_file() {
@@ -280,20 +507,20 @@ impl Target {
     }
 
     // 每次执行这个函数，都需要确保 `parts`, `earlist` 和 `output` 都被更新过
-    pub fn copy_with_times(&self) -> Result<()> {
+    pub fn copy_with_times(&self) -> Result<Copied> {
         let output = &self.output;
         // 判断是否需要拷贝
-        let need_copy = {
+        let copied = {
             if output.is_file() {
                 // 文件存在且hash相同，则跳过
-                if self.hash == get_file_md5(&output)? {
+                if self.hash == get_file_hash(&self.algorithm, output)? {
                     info!(file=?output, "🚚 copy skip with same hash");
-                    false
+                    Copied::Skipped
                 }
                 // 文件存在，hash不同，被修改过，则直接覆盖
                 else {
                     warn!(file=?output, "🚚 copy overwrite with different hash");
-                    true
+                    Copied::Overwritten
                 }
             }
             // 文件不存在，两种情况：
@@ -301,12 +528,12 @@ impl Target {
             // 2. 之前被处理过，但是被删除了
             else {
                 info!(file=?output, "🚚 copy with file not exist");
-                true
+                Copied::Created
             }
         };
         // 不需要拷贝文件，直接返回
-        if !need_copy {
-            return Ok(());
+        if copied == Copied::Skipped {
+            return Ok(copied);
         }
 
         let earliest = self.get_earliest()?;
@@ -322,28 +549,8 @@ impl Target {
         std::fs::copy(&self.path, output)?;
 
         // 设置拷贝文件的属性到最早时间
-        let st: SystemTime = earliest.into();
-        #[cfg(target_os = "windows")]
-        {
-            use std::os::windows::fs::FileTimesExt;
-            std::fs::File::options()
-                .write(true)
-                .open(output)?
-                .set_times(
-                    std::fs::FileTimes::new()
-                        .set_accessed(st)
-                        .set_modified(st)
-                        .set_created(st),
-                )?
-        }
-        #[cfg(any(target_os = "linux", target_os = "macos"))]
-        {
-            std::fs::File::options()
-                .write(true)
-                .open(output)?
-                .set_times(std::fs::FileTimes::new().set_accessed(st).set_modified(st))?
-        }
-        Ok(())
+        set_file_times(output, earliest)?;
+        Ok(copied)
     }
 }
 
@@ -363,7 +570,7 @@ mod tests {
     #[test]
     fn test_target() {
         let path = get_root().join("tests").join("2025/07/小鸡动画.gif");
-        let target = Target::new(path).unwrap();
+        let target = Target::new(path, "md5").unwrap();
         println!("target: {:#?}", target);
         assert_eq!(target.hash, "a6cc791ccd13f0dea507b0eb0f2c1b47");
         assert_eq!(target.extension, "gif");
@@ -373,7 +580,7 @@ mod tests {
     #[test]
     fn test_get_name() {
         let path = get_root().join("tests").join("2025/07/小鸡动画.gif");
-        let target = Target::new(path).unwrap();
+        let target = Target::new(path, "md5").unwrap();
         assert_eq!(target.get_name(1, Some("abc")), "abc_01.gif");
     }
 
@@ -382,7 +589,7 @@ mod tests {
         let path = get_root().join("tests");
         let output = OUTPUT_GEN(
             path.as_path(),
-            &vec![
+            &[
                 "2025".to_string(),
                 "07".to_string(),
                 "小鸡动画.gif".to_string(),
@@ -391,4 +598,46 @@ mod tests {
         println!("general output: {:?}", output);
         assert_eq!(output, path.join("2025").join("07").join("小鸡动画.gif"));
     }
+
+    #[test]
+    fn test_conflict() {
+        assert_eq!(
+            "Rename".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Rename
+        );
+        assert!("replace".parse::<ConflictPolicy>().is_err());
+        assert_eq!(ConflictPolicy::default(), ConflictPolicy::Trash);
+
+        let output = std::env::temp_dir().join("mmfplace_test_conflict");
+        let file = output.join("2002/11/a.jpg");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, b"a").unwrap();
+        let renamed = rename_conflict(&file, 3).unwrap();
+        assert_eq!(renamed, output.join("2002/11/a.conflict-3.jpg"));
+
+        // the same name in trash gets a suffix
+        for expected in ["a.jpg", "a.1.jpg"] {
+            std::fs::write(&file, b"a").unwrap();
+            let trash = move_to_trash(&file, &output, 3).unwrap();
+            assert_eq!(
+                trash,
+                output.join(TRASH_DIR).join("3/2002/11").join(expected)
+            );
+            assert!(!file.exists());
+        }
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    fn test_remove_empty_dirs() {
+        let root = std::env::temp_dir().join("mmfplace_test_remove_empty_dirs");
+        let file = root.join("2002/11/a.jpg");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(root.join("2002/b.jpg"), b"b").unwrap();
+        remove_empty_dirs(&file, &root);
+        // the non-empty directory and the root are kept
+        assert!(!root.join("2002/11").exists());
+        assert!(root.join("2002").is_dir());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
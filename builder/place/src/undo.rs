@@ -0,0 +1,447 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, delete_finfo, delete_override, query_actions, query_actions_after,
+    query_run_reverted, query_runs, set_run_reverted, set_run_undone, update_finfo,
+    upsert_override,
+};
+use super::target::{remove_empty_dirs, set_file_times};
+use utils::crypto::get_file_hash;
+
+/// the result of `place::undo`.
+#[derive(Debug, Clone, Default)]
+pub struct UndoReport {
+    pub run: i64,
+    /// the files copied by the run are removed
+    pub removed: usize,
+    /// the files moved or replaced by the run are restored
+    pub restored: usize,
+    /// the records changed by the run are reverted
+    pub reverted: usize,
+}
+
+impl UndoReport {
+    /// the lines printed by the `undo` command.
+    pub fn lines(&self) -> Vec<String> {
+        vec![format!(
+            "run {} undone: {} files removed, {} files restored, {} records reverted",
+            self.run, self.removed, self.restored, self.reverted
+        )]
+    }
+}
+
+// 按顺序重放修改，得到运行结束时每个归档文件应有的 hash，None 表示应该不存在
+fn expected_files(actions: &[ActionInfo]) -> HashMap<PathBuf, Option<&str>> {
+    let mut files = HashMap::new();
+    for action in actions {
+        let Some(destination) = &action.destination else {
+            continue;
+        };
+        match action.action.as_str() {
+            "copy" | "mtime" => {
+                files.insert(PathBuf::from(destination), Some(action.hash.as_str()));
+            }
+            "move" => {
+                files.insert(PathBuf::from(destination), Some(action.hash.as_str()));
+                if let Some(source) = &action.source {
+                    files.insert(PathBuf::from(source), None);
+                }
+            }
+            // 冲突时跳过的文件没有被修改
+            "delete" | "conflict-rename" | "conflict-overwrite" | "conflict-trash" => {
+                files.insert(PathBuf::from(destination), None);
+            }
+            _ => {}
+        }
+    }
+    files
+}
+
+// 运行之后归档文件被修改过，或者之后的运行修改过相同的文件，则不能撤销
+fn check(ctx: &Context, conn: &Connection, run_id: i64, actions: &[ActionInfo]) -> Result<()> {
+    let files = expected_files(actions);
+    for later in query_actions_after(conn, run_id)? {
+        let touched = later
+            .destination
+            .as_ref()
+            .is_some_and(|d| files.contains_key(Path::new(d)));
+        if touched || actions.iter().any(|a| a.hash == later.hash) {
+            return Err(anyhow::anyhow!(
+                "run {} changed the same files after run {}, undo it first",
+                later.run_id,
+                run_id
+            ));
+        }
+    }
+    for (file, hash) in files.iter() {
+        let modified = match hash {
+            Some(hash) => !file.is_file() || get_file_hash(ctx.hash_algorithm(), file)? != *hash,
+            None => file.exists(),
+        };
+        if modified {
+            return Err(anyhow::anyhow!(
+                "the archived file {:?} was modified since run {}",
+                file,
+                run_id
+            ));
+        }
+    }
+    for action in actions {
+        if let Some(trash) = &action.trash
+            && !Path::new(trash).is_file()
+        {
+            return Err(anyhow::anyhow!(
+                "the trash file {:?} not found, it can not be restored",
+                trash
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 还原为更新前的记录
+fn revert_record(conn: &Connection, action: &ActionInfo) -> Result<()> {
+    let previous = action.previous.as_deref().ok_or(anyhow::anyhow!(
+        "the previous record of {} not found",
+        action.hash
+    ))?;
+    let finfo: FileInfo<String> = serde_json::from_str(previous)?;
+    update_finfo(conn, &finfo)?;
+    Ok(())
+}
+
+// 已经完成的文件操作，数据库提交失败时按相反的顺序补偿
+#[derive(Debug)]
+enum Step {
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    // 删除的文件先移动到临时路径，提交之后再删除
+    Removed {
+        file: PathBuf,
+        staged: PathBuf,
+    },
+    Times {
+        file: PathBuf,
+        previous: DateTime<Local>,
+    },
+}
+
+fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::rename(from, to)
+}
+
+// 补偿失败时只能记录日志，需要手动处理
+fn compensate(steps: Vec<Step>) {
+    for step in steps.into_iter().rev() {
+        let result = match &step {
+            Step::Renamed { from, to } => rename(to, from).map_err(anyhow::Error::from),
+            Step::Removed { file, staged } => rename(staged, file).map_err(anyhow::Error::from),
+            Step::Times { file, previous } => set_file_times(file, *previous),
+        };
+        match result {
+            Ok(()) => info!(step=?step, "↩️ compensate the file operation"),
+            Err(e) => error!(step=?step, error=%e, "💥 compensate the file operation failed"),
+        }
+    }
+}
+
+// 提交之后删除临时文件并清理空目录
+fn finish(steps: Vec<Step>, root: &Path) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::Renamed { from, .. } => remove_empty_dirs(&from, root),
+            Step::Removed { file, staged } => {
+                std::fs::remove_file(staged)?;
+                remove_empty_dirs(&file, root);
+            }
+            Step::Times { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+fn revert(
+    conn: &Connection,
+    action: &ActionInfo,
+    undone: &mut UndoReport,
+    steps: &mut Vec<Step>,
+) -> Result<()> {
+    let destination = action.destination.as_deref().map(Path::new);
+    match (action.action.as_str(), destination) {
+        ("copy", Some(destination)) => {
+            if destination.is_file() {
+                let name = destination
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                let staged = destination.with_file_name(format!(".{}.mmfplace-undo", name));
+                std::fs::rename(destination, &staged)?;
+                steps.push(Step::Removed {
+                    file: destination.to_path_buf(),
+                    staged,
+                });
+                info!(file=?destination, "🗑️ remove the archived file");
+                undone.removed += 1;
+            }
+        }
+        ("delete" | "conflict-skip" | "conflict-rename" | "conflict-trash", _) => {}
+        ("conflict-overwrite", _) => {
+            warn!(file=?destination, "⚠️ the overwritten file can not be restored");
+        }
+        ("move", Some(destination)) => {
+            let source = action
+                .source
+                .as_deref()
+                .map(Path::new)
+                .ok_or(anyhow::anyhow!(
+                    "the source of the moved file {:?} not found",
+                    destination
+                ))?;
+            rename(destination, source)?;
+            steps.push(Step::Renamed {
+                from: destination.to_path_buf(),
+                to: source.to_path_buf(),
+            });
+            info!(from=?destination, to=?source, "↩️ move back the archived file");
+            undone.restored += 1;
+            revert_record(conn, action)?;
+            undone.reverted += 1;
+        }
+        // previous 是之前手动指定的时间，没有则删除
+        ("override", _) => {
+            match action.previous.as_deref().map(str::parse::<i64>) {
+                Some(earliest) => upsert_override(conn, &action.hash, earliest?)?,
+                None => delete_override(conn, &action.hash)?,
+            };
+            undone.reverted += 1;
+        }
+        // previous 是修复之前的修改时间
+        ("mtime", Some(destination)) => {
+            let previous = action
+                .previous
+                .as_deref()
+                .and_then(|p| p.parse::<i64>().ok())
+                .and_then(|p| Local.timestamp_opt(p, 0).single())
+                .ok_or(anyhow::anyhow!(
+                    "the previous modified time of {:?} not found",
+                    destination
+                ))?;
+            let current = std::fs::metadata(destination)?.modified()?.into();
+            set_file_times(destination, previous)?;
+            steps.push(Step::Times {
+                file: destination.to_path_buf(),
+                previous: current,
+            });
+            info!(file=?destination, modified=%previous, "↩️ restore the modified time");
+            undone.restored += 1;
+        }
+        ("insert", _) => {
+            delete_finfo(conn, &action.hash)?;
+            undone.reverted += 1;
+        }
+        ("update", _) => {
+            revert_record(conn, action)?;
+            undone.reverted += 1;
+        }
+        (other, _) => warn!(action = other, "⚠️ unknown action, skip it"),
+    }
+    // 恢复被覆盖或删除的文件
+    if let (Some(trash), Some(destination)) = (&action.trash, destination) {
+        rename(Path::new(trash), destination)?;
+        steps.push(Step::Renamed {
+            from: PathBuf::from(trash),
+            to: destination.to_path_buf(),
+        });
+        info!(file=?destination, trash=?trash, "♻️ restore from trash");
+        undone.restored += 1;
+    }
+    Ok(())
+}
+
+// 倒序撤销某次运行的所有修改，每个操作的文件修改和数据库还原一起完成，数据库提交失败时补偿文件修改，
+// 已经还原的操作数记录在运行中，中断后再次撤销时从剩余的操作继续。
+// 运行中更新的 last_seen、源文件路径 sources 和源文件索引 source_index 只用于追溯和增量扫描，不会还原
+pub fn do_undo(ctx: &Context, run_id: i64) -> Result<UndoReport> {
+    let conn = ctx.conn()?.lock().unwrap();
+    let run = query_runs(&conn)?
+        .into_iter()
+        .find(|r| r.id == run_id)
+        .ok_or(anyhow::anyhow!("run {} not found", run_id))?;
+    if run.undone.is_some() {
+        return Err(anyhow::anyhow!("run {} is already undone", run_id));
+    }
+    let root = PathBuf::from(run.output.unwrap_or_default());
+    let actions = query_actions(&conn, run_id)?;
+    let reverted = query_run_reverted(&conn, run_id)?.min(actions.len());
+    if reverted > 0 {
+        warn!(
+            run = run_id,
+            reverted,
+            total = actions.len(),
+            "⚠️ continue the interrupted undo"
+        );
+    }
+    // 倒序撤销，剩余的是开头的操作，撤销到这里时归档文件应有的状态由它们决定
+    let pending = &actions[..actions.len() - reverted];
+    check(ctx, &conn, run_id, pending)?;
+
+    let mut undone = UndoReport {
+        run: run_id,
+        ..Default::default()
+    };
+    for (i, action) in pending.iter().enumerate().rev() {
+        let mut steps = Vec::new();
+        let tx = conn.unchecked_transaction()?;
+        let result = revert(&tx, action, &mut undone, &mut steps)
+            .and_then(|()| Ok(set_run_reverted(&tx, run_id, actions.len() - i)?))
+            .and_then(|_| Ok(tx.commit()?));
+        if let Err(e) = result {
+            compensate(steps);
+            return Err(e.context(format!(
+                "undo the {} action of {} failed, {} of {} actions are undone, run undo again to continue",
+                action.action,
+                action.hash,
+                actions.len() - i - 1,
+                actions.len()
+            )));
+        }
+        finish(steps, &root)?;
+    }
+    set_run_undone(&conn, run_id, Local::now().timestamp())?;
+    info!(run = run_id, undone = ?undone, "undo run done");
+    Ok(undone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{RunRecord, insert_action, insert_run, update_run};
+    use std::borrow::Cow;
+
+    fn action(action: &str, hash: &str, destination: &str) -> ActionInfo {
+        ActionInfo {
+            hash: hash.to_string(),
+            destination: Some(destination.to_string()),
+            action: action.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_expected_files() {
+        let actions = vec![
+            action("insert", "hash1", "/to/2002/11/a.jpg"),
+            action("copy", "hash1", "/to/2002/11/a.jpg"),
+            // an earlier dated file with the same hash replaces it in the same run
+            action("delete", "hash1", "/to/2002/11/a.jpg"),
+            action("update", "hash1", "/to/2001/01/a.jpg"),
+            action("copy", "hash1", "/to/2001/01/a.jpg"),
+            action("conflict-trash", "hash2", "/to/2002/06/b.jpg"),
+            action("copy", "hash2", "/to/2002/06/b.jpg"),
+            action("conflict-skip", "hash3", "/to/2002/06/c.jpg"),
+            ActionInfo {
+                source: Some("/to/2002/06/d.jpg".to_string()),
+                ..action("move", "hash4", "/to/2002/07/d.jpg")
+            },
+            action("mtime", "hash5", "/to/2002/08/e.jpg"),
+        ];
+        let files = expected_files(&actions);
+        assert_eq!(files.len(), 6);
+        assert_eq!(files[Path::new("/to/2002/08/e.jpg")], Some("hash5"));
+        assert_eq!(files[Path::new("/to/2002/06/d.jpg")], None);
+        assert_eq!(files[Path::new("/to/2002/07/d.jpg")], Some("hash4"));
+        assert_eq!(files[Path::new("/to/2002/11/a.jpg")], None);
+        assert_eq!(files[Path::new("/to/2001/01/a.jpg")], Some("hash1"));
+        assert_eq!(files[Path::new("/to/2002/06/b.jpg")], Some("hash2"));
+    }
+
+    #[test]
+    fn test_undo_interrupted() {
+        let root = std::env::temp_dir().join("mmfplace_test_undo");
+        let _ = std::fs::remove_dir_all(&root);
+        let (a, from, to) = (
+            root.join("2002/11/a.jpg"),
+            root.join("2002/12/b.jpg"),
+            root.join("2003/01/b.jpg"),
+        );
+        for (file, content) in [(&a, "a"), (&to, "b")] {
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+        let ctx = Context::new(config::CONFIG.clone(), ":memory:");
+        let (hash_a, hash_b) = (
+            get_file_hash(ctx.hash_algorithm(), &a).unwrap(),
+            get_file_hash(ctx.hash_algorithm(), &to).unwrap(),
+        );
+        let run_id = {
+            let conn = ctx.conn().unwrap().lock().unwrap();
+            let run = insert_run(&conn, 100).unwrap();
+            let record = RunRecord {
+                id: run.id,
+                started: run.started,
+                output: Some(root.to_string_lossy().to_string()),
+                ..Default::default()
+            };
+            update_run(&conn, &record).unwrap();
+            // the previous record of the move is lost, it fails after the file is moved back
+            let moved = ActionInfo {
+                run_id: run.id,
+                source: Some(from.to_string_lossy().to_string()),
+                ..action("move", &hash_b, &to.to_string_lossy())
+            };
+            let copied = ActionInfo {
+                run_id: run.id,
+                ..action("copy", &hash_a, &a.to_string_lossy())
+            };
+            insert_action(&conn, &moved).unwrap();
+            insert_action(&conn, &copied).unwrap();
+            run.id
+        };
+
+        // the copy is undone and recorded, the move is compensated
+        assert!(do_undo(&ctx, run_id).is_err());
+        assert!(!a.exists() && !a.with_file_name(".a.jpg.mmfplace-undo").exists());
+        assert!(to.is_file() && !from.exists());
+        {
+            let conn = ctx.conn().unwrap().lock().unwrap();
+            assert_eq!(query_run_reverted(&conn, run_id).unwrap(), 1);
+            assert!(query_runs(&conn).unwrap()[0].undone.is_none());
+            let previous = FileInfo {
+                parts: Cow::Owned(vec![
+                    "2002".to_string(),
+                    "12".to_string(),
+                    "b.jpg".to_string(),
+                ]),
+                hash: Cow::Borrowed(hash_b.as_str()),
+                algorithm: Cow::Borrowed("md5"),
+                ..Default::default()
+            };
+            conn.execute(
+                "UPDATE actions SET previous = ? WHERE action = 'move'",
+                [serde_json::to_string(&previous).unwrap()],
+            )
+            .unwrap();
+        }
+
+        // continue with the remaining action
+        do_undo(&ctx, run_id).unwrap();
+        assert!(from.is_file() && !to.exists());
+        assert!(!root.join("2003").exists());
+        {
+            let conn = ctx.conn().unwrap().lock().unwrap();
+            assert!(query_runs(&conn).unwrap()[0].undone.is_some());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use chrono::{DateTime, Local, TimeZone};
+use rusqlite::Connection;
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use super::context::Context;
+use super::db::{
+    ActionInfo, FileInfo, RunInfo, finish_run, insert_action, insert_run, query_finfo_all,
+    query_sources,
+};
+use super::input::{Inputs, WalkOptions};
+use super::target::{
+    ConflictPolicy, Copied, OUTPUT_GEN, Target, apply_conflict_policy, is_internal, set_file_times,
+};
+use utils::crypto::get_file_hash;
+
+/// the archived file does not match the record in the database.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyIssue {
+    /// the record exists but the file does not
+    Missing,
+    /// the content is modified or corrupted
+    Mismatch,
+    /// the modified time is not the earliest date
+    Mtime,
+}
+
+impl std::fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            VerifyIssue::Missing => "missing",
+            VerifyIssue::Mismatch => "mismatch",
+            VerifyIssue::Mtime => "mtime",
+        };
+        f.write_str(s)
+    }
+}
+
+/// the result of repairing an issue, see `VerifyReport`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepairStatus {
+    Repaired,
+    /// no source found, or kept by the conflict policy `skip`
+    Skipped,
+    Failed,
+}
+
+/// the result of `place::verify`, the paths are relative to the output.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// the number of records checked
+    pub total: usize,
+    pub issues: Vec<(PathBuf, VerifyIssue)>,
+    /// the files in the archive without any record
+    pub orphans: Vec<PathBuf>,
+    /// the issues handled by the repair, empty if not repaired
+    pub repairs: Vec<(PathBuf, VerifyIssue, RepairStatus)>,
+    /// the run of the repair, it can be undone
+    pub run: Option<i64>,
+}
+
+impl VerifyReport {
+    pub fn count(&self, issue: VerifyIssue) -> usize {
+        self.issues.iter().filter(|(_, i)| *i == issue).count()
+    }
+
+    /// the lines printed by the `verify` command.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (file, issue) in self.issues.iter() {
+            lines.push(format!("{:<9} {}", issue.to_string(), file.display()));
+        }
+        for file in self.orphans.iter() {
+            lines.push(format!("{:<9} {}", "orphan", file.display()));
+        }
+        lines.push(format!(
+            "{} records checked: {} missing, {} mismatch, {} mtime, {} orphan",
+            self.total,
+            self.count(VerifyIssue::Missing),
+            self.count(VerifyIssue::Mismatch),
+            self.count(VerifyIssue::Mtime),
+            self.orphans.len()
+        ));
+        for (file, issue, status) in self.repairs.iter() {
+            let status = match status {
+                RepairStatus::Repaired => "repaired",
+                RepairStatus::Skipped => "skipped",
+                RepairStatus::Failed => "failed",
+            };
+            lines.push(format!("{:<9} {} ({})", status, file.display(), issue));
+        }
+        if let Some(run) = self.run {
+            let repaired = self
+                .repairs
+                .iter()
+                .filter(|(_, _, s)| *s == RepairStatus::Repaired)
+                .count();
+            lines.push(format!(
+                "{} of {} repaired, run {}",
+                repaired,
+                self.repairs.len(),
+                run
+            ));
+        }
+        lines
+    }
+}
+
+fn check(file: &Path, finfo: &FileInfo<String>) -> Result<Option<VerifyIssue>> {
+    if !file.is_file() {
+        return Ok(Some(VerifyIssue::Missing));
+    }
+    if get_file_hash(&finfo.algorithm, file)? != finfo.hash {
+        return Ok(Some(VerifyIssue::Mismatch));
+    }
+    let modified: DateTime<Local> = std::fs::metadata(file)?.modified()?.into();
+    if modified.timestamp() != finfo.earliest {
+        return Ok(Some(VerifyIssue::Mtime));
+    }
+    Ok(None)
+}
+
+// 归档中没有数据库记录的文件
+fn orphans(output: &Path, archived: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    let inputs = Inputs::Roots {
+        roots: vec![output.to_path_buf()],
+        walk: WalkOptions::default(),
+    };
+    let mut files: Vec<PathBuf> = inputs
+        .files(None)
+        .filter(|f| !is_internal(f, output) && !archived.contains(f))
+        .collect();
+    files.sort();
+    files
+}
+
+// 从记录的源文件中找到内容相同的文件
+fn find_source(conn: &Connection, finfo: &FileInfo<String>) -> Result<Option<PathBuf>> {
+    let mut candidates: Vec<String> = query_sources(conn, &finfo.hash)?
+        .into_iter()
+        .map(|s| s.path)
+        .collect();
+    candidates.extend(finfo.source.as_deref().map(str::to_string));
+    for path in candidates.into_iter().map(PathBuf::from) {
+        if path.is_file() && get_file_hash(&finfo.algorithm, &path)? == finfo.hash {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+struct Repair<'a> {
+    conn: &'a Connection,
+    output: PathBuf,
+    run: RunInfo,
+    policy: ConflictPolicy,
+}
+
+impl Repair<'_> {
+    fn record(
+        &self,
+        action: &str,
+        hash: &str,
+        source: &Path,
+        file: &Path,
+        trash: Option<PathBuf>,
+    ) -> Result<()> {
+        let action = ActionInfo {
+            run_id: self.run.id,
+            hash: hash.to_string(),
+            source: Some(source.to_string_lossy().to_string()),
+            destination: Some(file.to_string_lossy().to_string()),
+            action: action.to_string(),
+            timestamp: Local::now().timestamp(),
+            trash: trash.map(|t| t.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        insert_action(self.conn, &action)?;
+        Ok(())
+    }
+
+    // 丢失或被修改的文件从源文件重新拷贝，修改时间不一致的重新设置，返回是否修复
+    fn repair(&self, file: &Path, finfo: &FileInfo<String>, issue: VerifyIssue) -> Result<bool> {
+        if issue == VerifyIssue::Mtime {
+            let earliest = Local
+                .timestamp_opt(finfo.earliest, 0)
+                .single()
+                .ok_or(anyhow::anyhow!("invalid earliest {}", finfo.earliest))?;
+            // 记录修改前的时间，撤销时恢复
+            let modified: DateTime<Local> = std::fs::metadata(file)?.modified()?.into();
+            set_file_times(file, earliest)?;
+            let action = ActionInfo {
+                run_id: self.run.id,
+                hash: finfo.hash.to_string(),
+                destination: Some(file.to_string_lossy().to_string()),
+                action: "mtime".to_string(),
+                timestamp: Local::now().timestamp(),
+                previous: Some(modified.timestamp().to_string()),
+                ..Default::default()
+            };
+            insert_action(self.conn, &action)?;
+            return Ok(true);
+        }
+        let Some(source) = find_source(self.conn, finfo)? else {
+            warn!(file=?file, hash=%finfo.hash, "⚠️ no source found to repair");
+            return Ok(false);
+        };
+        if issue == VerifyIssue::Mismatch {
+            let policy = self.policy;
+            let moved = apply_conflict_policy(file, &self.output, self.run.id, policy)?;
+            self.record(
+                &format!("conflict-{}", policy),
+                &finfo.hash,
+                &source,
+                file,
+                moved,
+            )?;
+            if policy == ConflictPolicy::Skip {
+                return Ok(false);
+            }
+        }
+        let mut target =
+            Target::with_hash(source.clone(), finfo.hash.to_string(), &finfo.algorithm)?;
+        target.set_earliest(Some(finfo.earliest as u64))?;
+        target.output = file.to_path_buf();
+        if target.copy_with_times()? != Copied::Skipped {
+            self.record("copy", &finfo.hash, &source, file, None)?;
+        }
+        Ok(true)
+    }
+}
+
+// 对比归档目录和数据库记录，报告丢失、内容不一致、修改时间不一致和没有记录的文件
+pub async fn do_verify(ctx: &Context, output: PathBuf, repair: bool) -> Result<VerifyReport> {
+    let records = {
+        let conn = ctx.conn()?.lock().unwrap();
+        query_finfo_all(&conn)?
+    };
+    let total = records.len();
+    info!(total, output=?output, "start verify the archive");
+
+    let semaphore = Arc::new(Semaphore::new(ctx.batch()));
+    let mut handles = Vec::new();
+    for finfo in records {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let file = OUTPUT_GEN(&output, &finfo.parts);
+        handles.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let issue = check(&file, &finfo)?;
+            Ok::<_, anyhow::Error>((file, finfo, issue))
+        }));
+    }
+    let mut archived = HashSet::new();
+    let mut issues = Vec::new();
+    for handle in handles {
+        let (file, finfo, issue) = handle.await??;
+        archived.insert(file.clone());
+        if let Some(issue) = issue {
+            issues.push((file, finfo, issue));
+        }
+    }
+    issues.sort_by(|a, b| a.0.cmp(&b.0));
+    let orphans = orphans(&output, &archived);
+
+    let relative = |f: &Path| f.strip_prefix(&output).unwrap_or(f).to_path_buf();
+    let mut report = VerifyReport {
+        total,
+        issues: issues
+            .iter()
+            .map(|(file, _, issue)| (relative(file), *issue))
+            .collect(),
+        orphans: orphans.iter().map(|f| relative(f)).collect(),
+        ..Default::default()
+    };
+    if !repair || issues.is_empty() {
+        return Ok(report);
+    }
+
+    // 修复作为一次运行记录，可以通过 `undo` 撤销
+    let conn = ctx.conn()?.lock().unwrap();
+    let started = Local::now().timestamp();
+    let run = insert_run(&conn, started)?;
+    let repair = Repair {
+        conn: &conn,
+        output: output.clone(),
+        run,
+        policy: ctx.conflict_policy(),
+    };
+    let mut repaired = 0;
+    for (file, finfo, issue) in issues.iter() {
+        let status = match repair.repair(file, finfo, *issue) {
+            Ok(true) => {
+                repaired += 1;
+                RepairStatus::Repaired
+            }
+            Ok(false) => RepairStatus::Skipped,
+            Err(e) => {
+                warn!(file=?file, error=%e, "⚠️ repair failed");
+                RepairStatus::Failed
+            }
+        };
+        report.repairs.push((relative(file), *issue, status));
+    }
+    finish_run(
+        &conn,
+        run,
+        &output,
+        json!({ "verify": output }),
+        Some(json!({ "repair": true })),
+        json!({ "total": issues.len(), "repaired": repaired }),
+    )?;
+    report.run = Some(run.id);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::TRASH_DIR;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_check() {
+        let output = std::env::temp_dir().join("mmfplace_test_verify");
+        let file = output.join("2002/11/a.txt");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        let parts = vec!["2002".to_string(), "11".to_string(), "a.txt".to_string()];
+        let finfo = FileInfo {
+            parts: Cow::Owned(parts),
+            hash: Cow::Owned("0cc175b9c0f1b6a831c399e269772661".to_string()),
+            algorithm: Cow::Borrowed("md5"),
+            earliest: 1037404800,
+            ..Default::default()
+        };
+        assert_eq!(check(&file, &finfo).unwrap(), Some(VerifyIssue::Missing));
+        std::fs::write(&file, b"b").unwrap();
+        assert_eq!(check(&file, &finfo).unwrap(), Some(VerifyIssue::Mismatch));
+        std::fs::write(&file, b"a").unwrap();
+        assert_eq!(check(&file, &finfo).unwrap(), Some(VerifyIssue::Mtime));
+        let earliest = Local.timestamp_opt(finfo.earliest, 0).unwrap();
+        set_file_times(&file, earliest).unwrap();
+        assert_eq!(check(&file, &finfo).unwrap(), None);
+
+        std::fs::write(output.join(TRASH_DIR).with_extension("txt"), b"c").unwrap();
+        let trash = output.join(TRASH_DIR).join("1/2002/11/a.txt");
+        std::fs::create_dir_all(trash.parent().unwrap()).unwrap();
+        std::fs::write(&trash, b"a").unwrap();
+        assert!(is_internal(&trash, &output));
+        let archived = HashSet::from([file]);
+        assert_eq!(
+            orphans(&output, &archived),
+            vec![output.join(".mmfplace-trash.txt")]
+        );
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
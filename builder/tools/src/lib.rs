@@ -4,6 +4,7 @@ use std::path::Path;
 
 mod metadata;
 
-pub async fn metadata_extractor(file: &Path) -> Result<HashSet<String>> {
-    metadata::METADATA.read(file).await
+/// read the metadata of the file by metadata-extractor with the java runtime.
+pub async fn metadata_extractor(java: &str, file: &Path) -> Result<HashSet<String>> {
+    metadata::METADATA.read(java, file).await
 }
@@ -1,31 +1,30 @@
 use once_cell::sync::Lazy;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{Error, ErrorKind, Result};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tracing::{debug, error};
 
 const EXTRACTOR: &[u8] = include_bytes!("deps/metadata-extractor-2.19.0.jar");
 const XMPCORE: &[u8] = include_bytes!("deps/xmpcore-6.1.11.jar");
-pub(crate) static METADATA: Lazy<MetadataReader> = Lazy::new(|| MetadataReader::new());
+pub(crate) static METADATA: Lazy<MetadataReader> = Lazy::new(MetadataReader::new);
 
 #[derive(Debug)]
 pub struct MetadataReader {
-    java: String,
     extractor: PathBuf,
     xmpcore: PathBuf,
+    // 已经检查过的 java 运行环境，不同的调用者可以使用不同的 java
+    checked: Mutex<HashMap<String, bool>>,
 }
 
 impl MetadataReader {
     fn new() -> Self {
-        let tools = {
-            let mut work_dir =
-                std::env::current_exe().expect("failed to get current execute directory");
-            work_dir.pop();
-            work_dir.join("tools")
-        };
+        // 依赖工具可以随时重新释放，放在缓存目录中
+        let tools = config::cache_dir().join("tools");
         if !tools.is_dir() {
-            std::fs::create_dir_all(&tools).unwrap();
+            std::fs::create_dir_all(&tools)
+                .unwrap_or_else(|e| panic!("Failed to create {}: {}", tools.display(), e));
         }
         // free tools
         let (extractor, xmpcore) = (
@@ -41,22 +40,38 @@ impl MetadataReader {
             std::fs::write(&xmpcore, XMPCORE).expect("Failed to write xmpcore.jar");
         }
 
-        let java = std::env::var("MMFPLACE_JAVA").unwrap_or_else(|_| "java".to_string());
-        // check java runtime
-        let output = std::process::Command::new(&java).arg("-version").output();
-        if let Err(e) = output {
-            error!(java, error=?e, "💥 check java runtime failed");
-            std::process::exit(1);
-        }
         MetadataReader {
-            java,
             extractor,
             xmpcore,
+            checked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // check java runtime, only logged on the first failure
+    fn check_java(&self, java: &str) -> Result<()> {
+        let mut checked = self.checked.lock().unwrap();
+        let ok = *checked.entry(java.to_string()).or_insert_with(|| {
+            match std::process::Command::new(java).arg("-version").output() {
+                Ok(_) => true,
+                Err(e) => {
+                    error!(java, error=?e, "💥 check java runtime failed");
+                    false
+                }
+            }
+        });
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("java runtime {} is not available", java),
+            ))
         }
     }
 
     // #[tracing::instrument]
-    pub(crate) async fn read(&self, file: &Path) -> Result<HashSet<String>> {
+    pub(crate) async fn read(&self, java: &str, file: &Path) -> Result<HashSet<String>> {
+        self.check_java(java)?;
         let mut readers: HashSet<String> = HashSet::new();
         let class_path = format!(
             "{xc_jar}{c}{me_jar}",
@@ -73,7 +88,7 @@ impl MetadataReader {
         ];
         debug!(command=?args, "running metadata extractor.");
 
-        let mut child = tokio::process::Command::new(&self.java)
+        let mut child = tokio::process::Command::new(java)
             // .current_dir(file_path.as_ref())
             .args(args)
             // .stdin(std::process::Stdio::null())
@@ -142,9 +157,27 @@ mod tests {
         assert!(METADATA.xmpcore.is_file());
 
         let test = get_root().join("tests/2002/11/simple.jpg");
-        let readers = METADATA.read(test.as_path()).await.unwrap();
+        let readers = METADATA.read("java", test.as_path()).await.unwrap();
         println!("{:#?}", readers);
         assert!(readers.len() > 1);
         assert!(readers.contains("[Exif SubIFD] Date/Time Original = 2002:11:16 15:27:01"));
     }
+
+    #[tokio::test]
+    async fn test_read_missing_java() {
+        let test = get_root().join("tests/2002/11/simple.jpg");
+        let missing = "/nonexistent/mmfplace/java";
+        for _ in 0..2 {
+            let e = METADATA.read(missing, test.as_path()).await.unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::NotFound);
+        }
+        // other callers are not affected
+        assert!(
+            !METADATA
+                .read("java", test.as_path())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
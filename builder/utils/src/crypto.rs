@@ -1,50 +1,169 @@
-use digest::DynDigest;
+use digest::{DynDigest, InvalidBufferSize};
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::path::Path;
+
+/// the supported hash algorithms, `md5` is the default for compatibility.
+pub const HASH_ALGORITHMS: &[&str] = &["md5", "sha256", "blake3", "xxh3"];
+
+/// the 128-bit xxh3, wrapped to used as `DynDigest`.
+#[derive(Clone, Default)]
+struct Xxh3(xxhash_rust::xxh3::Xxh3);
+
+impl DynDigest for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize_into(self, buf: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        let mut this = self;
+        this.finalize_into_reset(buf)
+    }
+
+    fn finalize_into_reset(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        if out.len() != self.output_size() {
+            return Err(InvalidBufferSize);
+        }
+        out.copy_from_slice(&self.0.digest128().to_be_bytes());
+        self.0.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_size(&self) -> usize {
+        16
+    }
+
+    fn box_clone(&self) -> Box<dyn DynDigest> {
+        Box::new(self.clone())
+    }
+}
+
+/// the blake3 hasher, wrapped to used as `DynDigest`.
+#[derive(Clone, Default)]
+struct Blake3(blake3::Hasher);
+
+impl DynDigest for Blake3 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize_into(self, buf: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        let mut this = self;
+        this.finalize_into_reset(buf)
+    }
+
+    fn finalize_into_reset(&mut self, out: &mut [u8]) -> Result<(), InvalidBufferSize> {
+        if out.len() != self.output_size() {
+            return Err(InvalidBufferSize);
+        }
+        out.copy_from_slice(self.0.finalize().as_bytes());
+        self.0.reset();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn output_size(&self) -> usize {
+        blake3::OUT_LEN
+    }
+
+    fn box_clone(&self) -> Box<dyn DynDigest> {
+        Box::new(self.clone())
+    }
+}
 
 // DynDigest needs to be boxed here, since function return should be sized.
-fn select_hasher(s: &str) -> Box<dyn DynDigest> {
+fn select_hasher(s: &str) -> Option<Box<dyn DynDigest>> {
     match s {
         // cargo add md-5
-        "md5" => Box::new(md5::Md5::default()),
+        "md5" => Some(Box::new(md5::Md5::default())),
         // "sha1" => Box::new(sha1::Sha1::default()),
         // "sha224" => Box::new(sha2::Sha224::default()),
         // cargo add sha2
-        // "sha256" => Box::new(sha2::Sha256::default()),
+        "sha256" => Some(Box::new(sha2::Sha256::default())),
         // "sha384" => Box::new(sha2::Sha384::default()),
         // "sha512" => Box::new(sha2::Sha512::default()),
-        _ => unimplemented!("unsupported digest: {}", s),
+        // cargo add blake3
+        "blake3" => Some(Box::new(Blake3::default())),
+        // cargo add xxhash-rust --features xxh3
+        "xxh3" => Some(Box::new(Xxh3::default())),
+        _ => None,
     }
 }
 
 type HasherRet<T> = std::result::Result<T, std::io::Error>;
 
-fn file_hasher(hasher: &str, path: &PathBuf) -> HasherRet<String> {
+// 一次读取文件同时计算多个 hash，避免大文件重复读取，limit 为只读取文件开头的字节数
+fn file_hashers(algorithms: &[&str], path: &Path, limit: Option<u64>) -> HasherRet<Vec<String>> {
+    let mut hashers = algorithms
+        .iter()
+        .map(|a| {
+            select_hasher(a).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unsupported digest: {}", a),
+                )
+            })
+        })
+        .collect::<HasherRet<Vec<_>>>()?;
     let input = std::fs::File::open(path)?;
-    let mut reader = BufReader::new(input);
-
-    let digest = {
-        let mut hasher = select_hasher(hasher);
-        let mut buffer = [0; 1024];
-        loop {
-            let count = reader.read(&mut buffer)?;
-            if count == 0 {
-                break;
-            }
+    let mut reader = BufReader::new(input).take(limit.unwrap_or(u64::MAX));
+
+    let mut buffer = [0; 1024];
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        for hasher in hashers.iter_mut() {
             hasher.update(&buffer[..count]);
         }
-        hasher.finalize_reset()
-    };
-    Ok(base16ct::lower::encode_string(&digest))
+    }
+    Ok(hashers
+        .iter_mut()
+        .map(|h| base16ct::lower::encode_string(&h.finalize_reset()))
+        .collect())
+}
+
+fn file_hasher(hasher: &str, path: &Path) -> HasherRet<String> {
+    Ok(file_hashers(&[hasher], path, None)?.remove(0))
+}
+
+pub fn is_supported_hash(algorithm: &str) -> bool {
+    HASH_ALGORITHMS.contains(&algorithm)
+}
+
+/// the length of the hex digest, e.g. 32 for md5, none if not supported.
+pub fn hash_hex_len(algorithm: &str) -> Option<usize> {
+    select_hasher(algorithm).map(|h| h.output_size() * 2)
 }
 
-pub fn get_file_md5(path: &PathBuf) -> HasherRet<String> {
+pub fn get_file_hash(algorithm: &str, path: &Path) -> HasherRet<String> {
+    file_hasher(algorithm, path)
+}
+
+pub fn get_file_hashes(algorithms: &[&str], path: &Path) -> HasherRet<Vec<String>> {
+    file_hashers(algorithms, path, None)
+}
+
+/// hash only the first `limit` bytes of the file, used to quickly filter the candidate duplicates.
+pub fn get_file_partial_hash(algorithm: &str, path: &Path, limit: u64) -> HasherRet<String> {
+    Ok(file_hashers(&[algorithm], path, Some(limit))?.remove(0))
+}
+
+pub fn get_file_md5(path: &Path) -> HasherRet<String> {
     file_hasher("md5", path)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
 
     fn get_root() -> PathBuf {
         PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
@@ -62,4 +181,40 @@ mod tests {
         println!("md5: {}", md5);
         assert_eq!(md5, "a18932e314dbb4c81c6fd0e282d81d16");
     }
+
+    #[test]
+    fn test_file_hash() {
+        let path = get_root().join("tests/2002/11/simple.jpg");
+        // xxh3 is the 128 bits digest in big endian
+        let expected = [
+            ("md5", "a18932e314dbb4c81c6fd0e282d81d16"),
+            (
+                "sha256",
+                "0291b9bf797a3f59684c7e5817eb5b948796bc4271e004bc76515dabecadcee7",
+            ),
+            (
+                "blake3",
+                "08f606bca41fa9a0e2014b4a983a306595335158d0031fdb74dffa9dbca94b70",
+            ),
+            ("xxh3", "5350d7a61411a38d48b4c11b9ee6d785"),
+        ];
+        assert_eq!(expected.len(), HASH_ALGORITHMS.len());
+        for (algorithm, hash) in expected {
+            assert!(is_supported_hash(algorithm));
+            assert_eq!(get_file_hash(algorithm, &path).unwrap(), hash);
+            assert_eq!(hash_hex_len(algorithm), Some(hash.len()));
+        }
+        assert_eq!(hash_hex_len("sha1"), None);
+        assert!(get_file_hash("sha1", &path).is_err());
+
+        let hashes = get_file_hashes(&["md5", "xxh3"], &path).unwrap();
+        assert_eq!(hashes[0], "a18932e314dbb4c81c6fd0e282d81d16");
+        assert_eq!(hashes[1], get_file_hash("xxh3", &path).unwrap());
+
+        let size = std::fs::metadata(&path).unwrap().len();
+        let partial = get_file_partial_hash("md5", &path, 1024).unwrap();
+        assert_ne!(partial, hashes[0]);
+        let partial = get_file_partial_hash("md5", &path, size).unwrap();
+        assert_eq!(partial, hashes[0]);
+    }
 }
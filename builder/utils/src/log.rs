@@ -1,7 +1,29 @@
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, prelude::*};
 
+/// set when a progress line is drawn at the bottom of stderr, the next log line clears it first.
+pub static PROGRESS_LINE: AtomicBool = AtomicBool::new(false);
+
+// 输出日志前清除进度行，进度行会在下次刷新时重新绘制
+struct StderrWriter;
+
+impl Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut stderr = std::io::stderr().lock();
+        if PROGRESS_LINE.swap(false, Ordering::SeqCst) {
+            stderr.write_all(b"\r\x1b[2K")?;
+        }
+        stderr.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
 pub fn setup_tracing(
     verbose: bool,
     logfile: &Option<PathBuf>,
@@ -14,12 +36,12 @@ pub fn setup_tracing(
         Targets::default().with_default(Level::INFO)
     };
     let stdout_log = tracing_subscriber::fmt::layer()
-        .with_writer(std::io::stderr)
+        .with_writer(|| StderrWriter)
         .with_target(verbose)
         .with_line_number(verbose);
     let file_log = match logfile {
         Some(path) => {
-            let file = std::fs::File::create(&path).expect("Failed to open logfile");
+            let file = std::fs::File::create(path).expect("Failed to open logfile");
             // Some(tracing_subscriber::fmt::layer().json().with_writer(file))
             Some(tracing_subscriber::fmt::layer().with_writer(file))
         }